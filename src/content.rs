@@ -0,0 +1,463 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Error, ErrorKind, Read};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{self, Ordering};
+use std::{fmt, mem, ptr};
+
+use crate::error::Result;
+use crate::files::{self, FileOperation};
+#[cfg(feature = "image")]
+use crate::image::{ImageData, ImageFormat};
+use crate::mime::{ALLOWED_MIME_TYPES, HTML_MIME_TYPE, PASSWORD_MANAGER_HINT_MIME_TYPE};
+
+/// Content to store to a clipboard.
+///
+/// The content could hold multiple representations of the same data, each
+/// offered in its own set of mime types, so the pasting client could pick the
+/// one it understands best. The representations are offered in the order they
+/// were added.
+#[derive(Debug, Default)]
+pub struct ClipboardContent {
+    /// Payloads with the mime types they're offered in.
+    data: Vec<(Vec<String>, Payload)>,
+    /// Whether the content is sensitive, like a password.
+    sensitive: bool,
+    /// Number of complete transfers the content is served for.
+    max_transfers: Option<usize>,
+}
+
+impl ClipboardContent {
+    /// Create empty clipboard content.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `text` offered in all the supported text mime types.
+    pub fn with_text<T: Into<String>>(self, text: T) -> Self {
+        let mime_types = ALLOWED_MIME_TYPES.iter().map(|mime| mime.to_string()).collect();
+        self.with_data(mime_types, Payload::Data(text.into().into_bytes()))
+    }
+
+    /// Add `html` offered as HTML, with `alt_text` offered in all the
+    /// supported text mime types for clients which don't understand HTML.
+    pub fn with_html<H: Into<String>, T: Into<String>>(self, html: H, alt_text: T) -> Self {
+        self.with_mime(HTML_MIME_TYPE, html.into()).with_text(alt_text)
+    }
+
+    /// Add absolute file `paths` offered as URI list.
+    ///
+    /// The `operation` is advertised for file managers, so they could tell a
+    /// cut from a copy. Fails when some of the paths are not absolute.
+    pub fn with_files<P: AsRef<Path>>(self, paths: &[P], operation: FileOperation) -> Result<Self> {
+        let uri_list = files::encode_uri_list(paths)?;
+        // GNOME expects LF separated list without the trailing line ending.
+        let gnome_uris = uri_list.trim_end().replace("\r\n", "\n");
+        let gnome_copied_files = format!("{}\n{gnome_uris}", operation.as_str());
+        let content = self
+            .with_mime(files::URI_LIST_MIME_TYPE, uri_list)
+            .with_mime(files::GNOME_COPIED_FILES_MIME_TYPE, gnome_copied_files);
+
+        Ok(match operation {
+            FileOperation::Cut => content.with_mime(files::KDE_CUT_SELECTION_MIME_TYPE, "1"),
+            FileOperation::Copy => content,
+        })
+    }
+
+    /// Add `data` offered in the given `mime_type`.
+    ///
+    /// Data previously added for the same `mime_type` is replaced.
+    pub fn with_mime<M: Into<String>, D: Into<Vec<u8>>>(self, mime_type: M, data: D) -> Self {
+        self.with_data(vec![mime_type.into()], Payload::Data(data.into()))
+    }
+
+    /// Add `provider` producing data for the given `mime_types` on demand.
+    ///
+    /// The provider is called on the clipboard thread the first time the data
+    /// for one of the `mime_types` is requested by the pasting client or kept
+    /// in the clipboard history, the produced data is reused for the
+    /// consecutive requests. Data previously
+    /// added for the same mime types is replaced.
+    pub fn with_provider<I, M, P>(self, mime_types: I, provider: P) -> Self
+    where
+        I: IntoIterator<Item = M>,
+        M: Into<String>,
+        P: DataProvider + 'static,
+    {
+        let mime_types = mime_types.into_iter().map(Into::into).collect();
+        self.with_data(mime_types, Payload::Provider(Box::new(provider)))
+    }
+
+    /// Add `image` encoded into the given `format`.
+    ///
+    /// Fails when the image couldn't be encoded.
+    #[cfg(feature = "image")]
+    pub fn with_image(self, image: &ImageData, format: ImageFormat) -> Result<Self> {
+        let data = image.encode(format)?;
+        Ok(self.with_mime(format.mime_type(), data))
+    }
+
+    /// Add `provider` opening readers of the data for the given `mime_types`.
+    ///
+    /// The provider is called on the clipboard thread each time the data for
+    /// one of the `mime_types` is requested by the pasting client, and the
+    /// opened reader is streamed to it without holding the whole data in
+    /// memory. Data previously added for the same mime types is replaced.
+    pub fn with_stream_provider<I, M, P>(self, mime_types: I, provider: P) -> Self
+    where
+        I: IntoIterator<Item = M>,
+        M: Into<String>,
+        P: StreamProvider + 'static,
+    {
+        let mime_types = mime_types.into_iter().map(Into::into).collect();
+        self.with_data(mime_types, Payload::Stream(Box::new(provider)))
+    }
+
+    /// Add `reader` streaming data in the given `mime_type`.
+    ///
+    /// The reader could only be streamed once, so the consecutive requests for
+    /// the data get nothing. Use [`Self::with_stream_provider`] to serve
+    /// multiple requests.
+    pub fn with_reader<M: Into<String>>(self, mime_type: M, reader: Box<dyn Read + Send>) -> Self {
+        let mut reader = Some(reader);
+        self.with_stream_provider([mime_type], move |_: &str| {
+            reader.take().ok_or_else(|| Error::other("reader was already streamed"))
+        })
+    }
+
+    /// Add the file at `path` streaming data in the given `mime_type`.
+    ///
+    /// The file is opened each time the data is requested. Fails when the
+    /// file doesn't exist.
+    pub fn with_file<M: Into<String>, P: Into<PathBuf>>(
+        self,
+        mime_type: M,
+        path: P,
+    ) -> Result<Self> {
+        let path = path.into();
+        if !fs::metadata(&path)?.is_file() {
+            return Err(Error::new(ErrorKind::InvalidInput, "path is not a file").into());
+        }
+
+        Ok(self.with_stream_provider([mime_type], move |_: &str| File::open(&path)))
+    }
+
+    /// Mark the content as sensitive, like a password.
+    ///
+    /// The content is advertised with the password manager hint, so the
+    /// clipboard managers don't record it, and is overwritten with zeros once
+    /// it's replaced or dropped. Data replaced before the content is marked
+    /// isn't overwritten.
+    pub fn sensitive(mut self) -> Self {
+        self.sensitive = true;
+        self.with_mime(PASSWORD_MANAGER_HINT_MIME_TYPE, "secret")
+    }
+
+    /// Whether the content is marked as sensitive.
+    pub fn is_sensitive(&self) -> bool {
+        self.sensitive
+    }
+
+    /// Limit the number of pastes the content is served for.
+    ///
+    /// The content is withdrawn from the selection shortly after it's pasted
+    /// `transfers` times, with at least one paste always served. A paste
+    /// reading the content in several mime types is counted once, while
+    /// reading the same mime type again starts the next paste. Transfers of
+    /// the password manager hint are not counted.
+    ///
+    /// Clipboard managers usually copy the content right after it's stored,
+    /// using up a paste, unless the content is also marked as
+    /// [`Self::sensitive`].
+    pub fn max_transfers(mut self, transfers: usize) -> Self {
+        self.max_transfers = Some(transfers.max(1));
+        self
+    }
+
+    /// Mime types the content is offered in.
+    pub fn mime_types(&self) -> impl Iterator<Item = &str> {
+        self.data.iter().flat_map(|(mime_types, _)| mime_types.iter().map(String::as_str))
+    }
+
+    /// Whether the content has no data.
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    fn with_data(mut self, mime_types: Vec<String>, data: Payload) -> Self {
+        for (offered, _) in self.data.iter_mut() {
+            offered.retain(|mime| !mime_types.contains(mime));
+        }
+
+        let (kept, replaced): (Vec<_>, Vec<_>) =
+            mem::take(&mut self.data).into_iter().partition(|(offered, _)| !offered.is_empty());
+        self.data = kept;
+        self.data.push((mime_types, data));
+
+        // The payloads no longer offered in any mime type are dropped.
+        for (_, payload) in replaced {
+            if let (true, Payload::Data(mut data)) = (self.sensitive, payload) {
+                zero(&mut data);
+            }
+        }
+
+        self
+    }
+}
+
+impl Drop for ClipboardContent {
+    fn drop(&mut self) {
+        if !self.sensitive {
+            return;
+        }
+
+        for (_, data) in &mut self.data {
+            if let Payload::Data(data) = data {
+                zero(data);
+            }
+        }
+    }
+}
+
+/// Provider of the clipboard data produced on demand.
+///
+/// Implemented for closures taking the requested mime type.
+pub trait DataProvider: Send {
+    /// Produce data for the requested `mime_type`.
+    fn provide(&mut self, mime_type: &str) -> Vec<u8>;
+}
+
+impl<F: FnMut(&str) -> Vec<u8> + Send> DataProvider for F {
+    fn provide(&mut self, mime_type: &str) -> Vec<u8> {
+        self(mime_type)
+    }
+}
+
+/// Provider of the clipboard data streamed to the pasting client.
+///
+/// Implemented for closures taking the requested mime type.
+pub trait StreamProvider: Send {
+    /// Open a reader of the data for the requested `mime_type`.
+    fn open(&mut self, mime_type: &str) -> io::Result<Box<dyn Read + Send>>;
+}
+
+impl<F, R> StreamProvider for F
+where
+    F: FnMut(&str) -> io::Result<R> + Send,
+    R: Read + Send + 'static,
+{
+    fn open(&mut self, mime_type: &str) -> io::Result<Box<dyn Read + Send>> {
+        Ok(Box::new(self(mime_type)?))
+    }
+}
+
+/// Data of the clipboard content.
+enum Payload {
+    /// Data produced up front.
+    Data(Vec<u8>),
+    /// Data produced on demand.
+    Provider(Box<dyn DataProvider>),
+    /// Data streamed on demand.
+    Stream(Box<dyn StreamProvider>),
+}
+
+impl fmt::Debug for Payload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Data(data) => f.debug_tuple("Data").field(&data.len()).finish(),
+            Self::Provider(_) => f.write_str("Provider"),
+            Self::Stream(_) => f.write_str("Stream"),
+        }
+    }
+}
+
+/// HTML loaded from a clipboard.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HtmlContent {
+    /// The clipboard offered HTML.
+    Html(String),
+    /// The clipboard didn't offer HTML, so plain text was loaded instead.
+    Text(String),
+}
+
+/// Content of the selection we're serving.
+#[derive(Debug, Default)]
+pub struct SelectionContent {
+    /// Offered mime types in the order of preference.
+    mime_types: Vec<String>,
+    /// Data for each offered mime type.
+    data: HashMap<String, SelectionData>,
+    /// Whether the content should be overwritten with zeros once dropped.
+    sensitive: bool,
+    /// Number of complete transfers the content is served for.
+    max_transfers: Option<usize>,
+}
+
+impl SelectionContent {
+    /// Mime types the content is offered in.
+    pub fn mime_types(&self) -> &[String] {
+        &self.mime_types
+    }
+
+    /// Get data for the given `mime_type`.
+    ///
+    /// The data is produced by the provider if it wasn't yet.
+    pub fn get(&mut self, mime_type: &str) -> Option<SendData> {
+        let data = self.data.get_mut(mime_type)?;
+        let provider = match data {
+            SelectionData::Ready(data) => return Some(SendData::Data(data.clone())),
+            SelectionData::Stream(provider) => {
+                return provider.borrow_mut().open(mime_type).ok().map(SendData::Reader);
+            },
+            SelectionData::Lazy(provider) => provider.clone(),
+        };
+
+        let produced = provider.borrow_mut().provide(mime_type);
+        let produced = Rc::new(Bytes { data: produced, sensitive: self.sensitive });
+        *data = SelectionData::Ready(produced.clone());
+        Some(SendData::Data(produced))
+    }
+
+    /// Whether the data for the given `mime_type` is streamed.
+    pub fn is_streamed(&self, mime_type: &str) -> bool {
+        matches!(self.data.get(mime_type), Some(SelectionData::Stream(_)))
+    }
+
+    /// Number of complete transfers the content is served for.
+    pub fn max_transfers(&self) -> Option<usize> {
+        self.max_transfers
+    }
+
+    /// Copy of the data for each mime type, produced by the provider if it
+    /// wasn't yet.
+    ///
+    /// Streamed data and sensitive content are never copied.
+    pub fn copy_data(&mut self) -> Vec<(String, Vec<u8>)> {
+        if self.sensitive {
+            return Vec::new();
+        }
+
+        let mut copy = Vec::new();
+        for mime_type in self.mime_types.clone() {
+            if self.is_streamed(&mime_type) {
+                continue;
+            }
+
+            if let Some(SendData::Data(data)) = self.get(&mime_type) {
+                copy.push((mime_type, data.to_vec()));
+            }
+        }
+        copy
+    }
+
+    /// Drop the content.
+    ///
+    /// Sensitive data is overwritten with zeros once the last send of it is
+    /// done.
+    pub fn clear(&mut self) {
+        self.mime_types.clear();
+        self.data.clear();
+    }
+}
+
+impl From<ClipboardContent> for SelectionContent {
+    fn from(mut content: ClipboardContent) -> Self {
+        let mut selection = Self {
+            sensitive: content.sensitive,
+            max_transfers: content.max_transfers,
+            ..Default::default()
+        };
+        for (mime_types, data) in mem::take(&mut content.data) {
+            // Share the same payload between all of its mime types.
+            let data = match data {
+                Payload::Data(data) => {
+                    SelectionData::Ready(Rc::new(Bytes { data, sensitive: content.sensitive }))
+                },
+                Payload::Provider(provider) => SelectionData::Lazy(Rc::new(RefCell::new(provider))),
+                Payload::Stream(provider) => SelectionData::Stream(Rc::new(RefCell::new(provider))),
+            };
+            for mime_type in mime_types {
+                selection.data.insert(mime_type.clone(), data.clone());
+                selection.mime_types.push(mime_type);
+            }
+        }
+
+        selection
+    }
+}
+
+/// Data of the selection for a single mime type.
+#[derive(Clone)]
+enum SelectionData {
+    /// Data ready to be sent.
+    Ready(Rc<Bytes>),
+    /// Data yet to be produced by the provider.
+    Lazy(Rc<RefCell<Box<dyn DataProvider>>>),
+    /// Data streamed from the readers opened by the provider.
+    Stream(Rc<RefCell<Box<dyn StreamProvider>>>),
+}
+
+impl fmt::Debug for SelectionData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ready(data) => f.debug_tuple("Ready").field(&data.len()).finish(),
+            Self::Lazy(_) => f.write_str("Lazy"),
+            Self::Stream(_) => f.write_str("Stream"),
+        }
+    }
+}
+
+/// Data to send to the pasting client.
+pub enum SendData {
+    /// Data held in memory.
+    Data(Rc<Bytes>),
+    /// Data streamed from the reader.
+    Reader(Box<dyn Read + Send>),
+}
+
+/// Data held in memory, shared between the mime types and the sends.
+pub struct Bytes {
+    data: Vec<u8>,
+    /// Whether the data should be overwritten with zeros once dropped.
+    sensitive: bool,
+}
+
+impl Deref for Bytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl Drop for Bytes {
+    fn drop(&mut self) {
+        if self.sensitive {
+            zero(&mut self.data);
+        }
+    }
+}
+
+/// Overwrite the `data` with zeros.
+fn zero(data: &mut [u8]) {
+    #[cfg(test)]
+    tests::ZEROED.lock().unwrap().push(data.to_vec());
+
+    // Write through volatile pointers, so the writes to the data about to be
+    // freed aren't optimized away.
+    for byte in data.iter_mut() {
+        unsafe { ptr::write_volatile(byte, 0) };
+    }
+    atomic::compiler_fence(Ordering::SeqCst);
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::sync::Mutex;
+
+    /// Data overwritten with zeros, as it was before.
+    pub static ZEROED: Mutex<Vec<Vec<u8>>> = Mutex::new(Vec::new());
+}
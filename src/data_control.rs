@@ -0,0 +1,512 @@
+use std::os::unix::io::{AsFd, OwnedFd};
+use std::sync::Mutex;
+
+use sctk::data_device_manager::WritePipe;
+use sctk::reexports::client::globals::{BindError, GlobalList};
+use sctk::reexports::client::protocol::wl_seat::WlSeat;
+use sctk::reexports::client::{Connection, Dispatch, Proxy, QueueHandle, event_created_child};
+use sctk::reexports::protocols_wlr::data_control::v1::client::zwlr_data_control_device_v1::{
+    self, ZwlrDataControlDeviceV1,
+};
+use sctk::reexports::protocols_wlr::data_control::v1::client::zwlr_data_control_manager_v1::ZwlrDataControlManagerV1;
+use sctk::reexports::protocols_wlr::data_control::v1::client::zwlr_data_control_offer_v1::{
+    self, ZwlrDataControlOfferV1,
+};
+use sctk::reexports::protocols_wlr::data_control::v1::client::zwlr_data_control_source_v1::{
+    self, ZwlrDataControlSourceV1,
+};
+use wayland_backend::client::ObjectId;
+use wayland_protocols::ext::data_control::v1::client::ext_data_control_device_v1::{
+    self, ExtDataControlDeviceV1,
+};
+use wayland_protocols::ext::data_control::v1::client::ext_data_control_manager_v1::ExtDataControlManagerV1;
+use wayland_protocols::ext::data_control::v1::client::ext_data_control_offer_v1::{
+    self, ExtDataControlOfferV1,
+};
+use wayland_protocols::ext::data_control::v1::client::ext_data_control_source_v1::{
+    self, ExtDataControlSourceV1,
+};
+
+use crate::state::{ClipboardBackend, SelectionTarget, State};
+
+/// The first version of the wlr data control protocol with primary selection.
+const WLR_PRIMARY_SELECTION_SINCE: u32 = 2;
+
+/// Access to the selections without keyboard focus through the data control
+/// protocol.
+///
+/// The `ext` protocol is preferred, with the `wlr` one used by older
+/// compositors as a fallback.
+#[derive(Debug)]
+pub struct DataControlState {
+    manager: DataControlManager,
+}
+
+#[derive(Debug)]
+enum DataControlManager {
+    Ext(ExtDataControlManagerV1),
+    Wlr(ZwlrDataControlManagerV1),
+}
+
+impl DataControlState {
+    pub fn bind(
+        globals: &GlobalList,
+        queue_handle: &QueueHandle<State>,
+    ) -> Result<Self, BindError> {
+        let manager = match globals.bind(queue_handle, 1..=1, ()) {
+            Ok(manager) => DataControlManager::Ext(manager),
+            Err(_) => DataControlManager::Wlr(globals.bind(queue_handle, 1..=2, ())?),
+        };
+
+        Ok(Self { manager })
+    }
+
+    /// The protocol used to access the selections.
+    pub fn backend(&self) -> ClipboardBackend {
+        match self.manager {
+            DataControlManager::Ext(_) => ClipboardBackend::ExtDataControl,
+            DataControlManager::Wlr(_) => ClipboardBackend::WlrDataControl,
+        }
+    }
+
+    /// Whether the selection for the given target could be accessed.
+    ///
+    /// The primary selection was added to the `wlr` protocol later.
+    pub fn supports(&self, ty: SelectionTarget) -> bool {
+        match (&self.manager, ty) {
+            (DataControlManager::Wlr(manager), SelectionTarget::Primary) => {
+                manager.version() >= WLR_PRIMARY_SELECTION_SINCE
+            },
+            _ => true,
+        }
+    }
+
+    /// Get the data control device for the `seat`.
+    pub fn get_data_device(
+        &self,
+        queue_handle: &QueueHandle<State>,
+        seat: &WlSeat,
+    ) -> DataControlDevice {
+        let data = DataControlDeviceData {
+            seat: seat.clone(),
+            selection: Default::default(),
+            primary_selection: Default::default(),
+        };
+
+        match &self.manager {
+            DataControlManager::Ext(manager) => {
+                DataControlDevice::Ext(manager.get_data_device(seat, queue_handle, data))
+            },
+            DataControlManager::Wlr(manager) => {
+                DataControlDevice::Wlr(manager.get_data_device(seat, queue_handle, data))
+            },
+        }
+    }
+
+    /// Create the source offering `mime_types` for the given target.
+    pub fn create_source(
+        &self,
+        queue_handle: &QueueHandle<State>,
+        ty: SelectionTarget,
+        mime_types: &[String],
+    ) -> DataControlSource {
+        match &self.manager {
+            DataControlManager::Ext(manager) => {
+                let source = manager.create_data_source(queue_handle, ty);
+                for mime_type in mime_types {
+                    source.offer(mime_type.clone());
+                }
+                DataControlSource::Ext(source)
+            },
+            DataControlManager::Wlr(manager) => {
+                let source = manager.create_data_source(queue_handle, ty);
+                for mime_type in mime_types {
+                    source.offer(mime_type.clone());
+                }
+                DataControlSource::Wlr(source)
+            },
+        }
+    }
+}
+
+/// Data control device of the seat.
+#[derive(Debug)]
+pub enum DataControlDevice {
+    Ext(ExtDataControlDeviceV1),
+    Wlr(ZwlrDataControlDeviceV1),
+}
+
+impl DataControlDevice {
+    /// Set the selection for the given target, clearing it without `source`.
+    pub fn set_selection(&self, ty: SelectionTarget, source: Option<&DataControlSource>) {
+        // Sources are created by the manager of the device.
+        match self {
+            Self::Ext(device) => {
+                let source = source.map(|source| match source {
+                    DataControlSource::Ext(source) => source,
+                    DataControlSource::Wlr(_) => unreachable!(),
+                });
+                match ty {
+                    SelectionTarget::Clipboard => device.set_selection(source),
+                    SelectionTarget::Primary => device.set_primary_selection(source),
+                }
+            },
+            Self::Wlr(device) => {
+                let source = source.map(|source| match source {
+                    DataControlSource::Wlr(source) => source,
+                    DataControlSource::Ext(_) => unreachable!(),
+                });
+                match ty {
+                    SelectionTarget::Clipboard => device.set_selection(source),
+                    SelectionTarget::Primary => device.set_primary_selection(source),
+                }
+            },
+        }
+    }
+
+    /// Whether the selection for the given target could be accessed.
+    pub fn supports(&self, ty: SelectionTarget) -> bool {
+        match (self, ty) {
+            (Self::Wlr(device), SelectionTarget::Primary) => {
+                device.version() >= WLR_PRIMARY_SELECTION_SINCE
+            },
+            _ => true,
+        }
+    }
+
+    /// Get the current selection offer for the given target.
+    pub fn selection_offer(&self, ty: SelectionTarget) -> Option<DataControlOffer> {
+        let data = match self {
+            Self::Ext(device) => device.data::<DataControlDeviceData>()?,
+            Self::Wlr(device) => device.data::<DataControlDeviceData>()?,
+        };
+
+        data.offer(ty).lock().unwrap().clone()
+    }
+}
+
+impl Drop for DataControlDevice {
+    fn drop(&mut self) {
+        match self {
+            Self::Ext(device) => device.destroy(),
+            Self::Wlr(device) => device.destroy(),
+        }
+    }
+}
+
+/// Data of the data control device.
+#[derive(Debug)]
+pub struct DataControlDeviceData {
+    seat: WlSeat,
+    selection: Mutex<Option<DataControlOffer>>,
+    primary_selection: Mutex<Option<DataControlOffer>>,
+}
+
+impl DataControlDeviceData {
+    fn offer(&self, ty: SelectionTarget) -> &Mutex<Option<DataControlOffer>> {
+        match ty {
+            SelectionTarget::Clipboard => &self.selection,
+            SelectionTarget::Primary => &self.primary_selection,
+        }
+    }
+
+    /// Replace the selection offer, destroying the old one.
+    fn set_offer(&self, ty: SelectionTarget, offer: Option<DataControlOffer>) {
+        let old = std::mem::replace(&mut *self.offer(ty).lock().unwrap(), offer.clone());
+
+        let other = match ty {
+            SelectionTarget::Clipboard => SelectionTarget::Primary,
+            SelectionTarget::Primary => SelectionTarget::Clipboard,
+        };
+        let other = self.offer(other).lock().unwrap().clone();
+
+        // The same offer could be used for both selections.
+        if let Some(old) =
+            old.filter(|old| Some(old) != offer.as_ref() && Some(old) != other.as_ref())
+        {
+            old.destroy();
+        }
+    }
+}
+
+/// Selection source served through the data control device.
+#[derive(Debug)]
+pub enum DataControlSource {
+    Ext(ExtDataControlSourceV1),
+    Wlr(ZwlrDataControlSourceV1),
+}
+
+impl DataControlSource {
+    pub fn id(&self) -> ObjectId {
+        match self {
+            Self::Ext(source) => source.id(),
+            Self::Wlr(source) => source.id(),
+        }
+    }
+}
+
+impl Drop for DataControlSource {
+    fn drop(&mut self) {
+        match self {
+            Self::Ext(source) => source.destroy(),
+            Self::Wlr(source) => source.destroy(),
+        }
+    }
+}
+
+/// Selection offer received through the data control device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DataControlOffer {
+    Ext(ExtDataControlOfferV1),
+    Wlr(ZwlrDataControlOfferV1),
+}
+
+impl DataControlOffer {
+    pub fn with_mime_types<T, F: Fn(&[String]) -> T>(&self, callback: F) -> T {
+        let data = match self {
+            Self::Ext(offer) => offer.data::<DataControlOfferData>(),
+            Self::Wlr(offer) => offer.data::<DataControlOfferData>(),
+        };
+
+        match data {
+            Some(data) => callback(&data.mime_types.lock().unwrap()),
+            None => callback(&[]),
+        }
+    }
+
+    /// Request the content of `mime_type` to be written into `write_fd`.
+    pub fn receive_to_fd(&self, mime_type: String, write_fd: OwnedFd) {
+        match self {
+            Self::Ext(offer) => offer.receive(mime_type, write_fd.as_fd()),
+            Self::Wlr(offer) => offer.receive(mime_type, write_fd.as_fd()),
+        }
+    }
+
+    fn destroy(&self) {
+        match self {
+            Self::Ext(offer) => offer.destroy(),
+            Self::Wlr(offer) => offer.destroy(),
+        }
+    }
+}
+
+/// Data of the data control offer.
+#[derive(Debug, Default)]
+pub struct DataControlOfferData {
+    mime_types: Mutex<Vec<String>>,
+}
+
+/// Implement the dispatching for one of the data control protocols, which
+/// only differ in their names.
+macro_rules! dispatch_data_control {
+    (
+        $variant:ident,
+        $manager:ty,
+        $device:ty,
+        $device_mod:ident,
+        $offer:ty,
+        $offer_mod:ident,
+        $source:ty,
+        $source_mod:ident
+    ) => {
+        impl Dispatch<$manager, ()> for State {
+            fn event(
+                _: &mut State,
+                _: &$manager,
+                _: <$manager as Proxy>::Event,
+                _: &(),
+                _: &Connection,
+                _: &QueueHandle<State>,
+            ) {
+            }
+        }
+
+        impl Dispatch<$device, DataControlDeviceData> for State {
+            event_created_child!(State, $device, [
+                $device_mod::EVT_DATA_OFFER_OPCODE => ($offer, DataControlOfferData::default()),
+            ]);
+
+            fn event(
+                state: &mut State,
+                _: &$device,
+                event: $device_mod::Event,
+                data: &DataControlDeviceData,
+                _: &Connection,
+                _: &QueueHandle<State>,
+            ) {
+                use $device_mod::Event;
+                match event {
+                    Event::Selection { id } => {
+                        data.set_offer(SelectionTarget::Clipboard, id.map(DataControlOffer::$variant));
+                        state.notify_selection_change(SelectionTarget::Clipboard, &data.seat);
+                    },
+                    Event::PrimarySelection { id } => {
+                        data.set_offer(SelectionTarget::Primary, id.map(DataControlOffer::$variant));
+                        state.notify_selection_change(SelectionTarget::Primary, &data.seat);
+                    },
+                    Event::Finished => state.data_control_finished(&data.seat),
+                    // The offer is tracked once it becomes the selection.
+                    _ => (),
+                }
+            }
+        }
+
+        impl Dispatch<$offer, DataControlOfferData> for State {
+            fn event(
+                _: &mut State,
+                _: &$offer,
+                event: $offer_mod::Event,
+                data: &DataControlOfferData,
+                _: &Connection,
+                _: &QueueHandle<State>,
+            ) {
+                if let $offer_mod::Event::Offer { mime_type } = event {
+                    data.mime_types.lock().unwrap().push(mime_type);
+                }
+            }
+        }
+
+        impl Dispatch<$source, SelectionTarget> for State {
+            fn event(
+                state: &mut State,
+                source: &$source,
+                event: $source_mod::Event,
+                ty: &SelectionTarget,
+                _: &Connection,
+                _: &QueueHandle<State>,
+            ) {
+                use $source_mod::Event;
+                match event {
+                    Event::Send { mime_type, fd } => {
+                        state.send_request(*ty, source.id(), WritePipe::from(fd), mime_type);
+                    },
+                    Event::Cancelled => state.source_cancelled(*ty, source.id()),
+                    _ => (),
+                }
+            }
+        }
+    };
+}
+
+dispatch_data_control!(
+    Ext,
+    ExtDataControlManagerV1,
+    ExtDataControlDeviceV1,
+    ext_data_control_device_v1,
+    ExtDataControlOfferV1,
+    ext_data_control_offer_v1,
+    ExtDataControlSourceV1,
+    ext_data_control_source_v1
+);
+
+dispatch_data_control!(
+    Wlr,
+    ZwlrDataControlManagerV1,
+    ZwlrDataControlDeviceV1,
+    zwlr_data_control_device_v1,
+    ZwlrDataControlOfferV1,
+    zwlr_data_control_offer_v1,
+    ZwlrDataControlSourceV1,
+    zwlr_data_control_source_v1
+);
+
+#[cfg(test)]
+mod tests {
+    use crate::ClipboardError;
+    use crate::state::{ClipboardBackend, SelectionTarget};
+    use crate::test_compositor::{DataControlProtocol, TestCompositor, connect};
+
+    #[test]
+    fn loads_without_focus() {
+        let (compositor, _connection, clipboard) = connect(DataControlProtocol::ext());
+        assert_eq!(clipboard.backend().unwrap(), ClipboardBackend::ExtDataControl);
+
+        compositor.set_selection(SelectionTarget::Clipboard, &[("text/plain", b"external")]);
+        TestCompositor::wait_for(|| clipboard.load().ok().as_deref() == Some("external"));
+
+        compositor.set_selection(SelectionTarget::Primary, &[("text/plain", b"primary")]);
+        TestCompositor::wait_for(|| clipboard.load_primary().ok().as_deref() == Some("primary"));
+    }
+
+    #[test]
+    fn stores_without_focus() {
+        let (compositor, _connection, clipboard) = connect(DataControlProtocol::ext());
+
+        let stored = clipboard.try_store("ours").unwrap();
+        TestCompositor::wait_for(|| {
+            compositor.client_selection(SelectionTarget::Clipboard).is_some()
+        });
+        let text =
+            compositor.read_selection(SelectionTarget::Clipboard, "text/plain;charset=utf-8");
+        assert_eq!(text, b"ours");
+        assert!(!stored.is_cancelled());
+
+        compositor.set_selection(SelectionTarget::Clipboard, &[("text/plain", b"external")]);
+        TestCompositor::wait_for(|| stored.is_cancelled());
+        assert!(!clipboard.owns_selection());
+        TestCompositor::wait_for(|| clipboard.load().ok().as_deref() == Some("external"));
+    }
+
+    #[test]
+    fn rejected_store_fails() {
+        let (compositor, _connection, clipboard) = connect(DataControlProtocol::ext());
+        compositor.set_ignore_client_selections(true);
+
+        assert!(matches!(clipboard.try_store("ours"), Err(ClipboardError::Rejected)));
+        assert!(!clipboard.owns_selection());
+
+        compositor.set_ignore_client_selections(false);
+        let stored = clipboard.try_store("ours").unwrap();
+        assert!(clipboard.owns_selection());
+        assert!(!stored.is_cancelled());
+    }
+
+    #[test]
+    fn stores_primary_without_focus() {
+        let (compositor, _connection, clipboard) = connect(DataControlProtocol::ext());
+
+        let stored = clipboard.try_store_primary("ours").unwrap();
+        TestCompositor::wait_for(|| {
+            compositor.client_selection(SelectionTarget::Primary).is_some()
+        });
+        let text = compositor.read_selection(SelectionTarget::Primary, "text/plain;charset=utf-8");
+        assert_eq!(text, b"ours");
+
+        clipboard.clear_primary();
+        TestCompositor::wait_for(|| stored.is_cancelled());
+        assert!(compositor.client_selection(SelectionTarget::Primary).is_none());
+    }
+
+    #[test]
+    fn falls_back_to_wlr() {
+        let (compositor, _connection, clipboard) = connect(DataControlProtocol::wlr(2));
+        assert_eq!(clipboard.backend().unwrap(), ClipboardBackend::WlrDataControl);
+
+        compositor.set_selection(SelectionTarget::Primary, &[("text/plain", b"external")]);
+        TestCompositor::wait_for(|| clipboard.load_primary().ok().as_deref() == Some("external"));
+
+        let stored = clipboard.try_store("ours").unwrap();
+        TestCompositor::wait_for(|| {
+            compositor.client_selection(SelectionTarget::Clipboard).is_some()
+        });
+        let text =
+            compositor.read_selection(SelectionTarget::Clipboard, "text/plain;charset=utf-8");
+        assert_eq!(text, b"ours");
+
+        compositor.set_selection(SelectionTarget::Clipboard, &[("text/plain", b"external")]);
+        TestCompositor::wait_for(|| stored.is_cancelled());
+    }
+
+    #[test]
+    fn wlr_without_primary_selection() {
+        let (compositor, _connection, clipboard) = connect(DataControlProtocol::wlr(1));
+        assert_eq!(clipboard.backend().unwrap(), ClipboardBackend::WlrDataControl);
+
+        compositor.set_selection(SelectionTarget::Clipboard, &[("text/plain", b"external")]);
+        TestCompositor::wait_for(|| clipboard.load().ok().as_deref() == Some("external"));
+
+        // The compositor has no primary selection protocol to fall back to.
+        let err = clipboard.load_primary().unwrap_err();
+        assert!(matches!(err, ClipboardError::ProtocolUnavailable));
+    }
+}
@@ -0,0 +1,91 @@
+use std::{fmt, io};
+
+/// Result of the clipboard operations.
+pub type Result<T> = std::result::Result<T, ClipboardError>;
+
+/// Clipboard operation error.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ClipboardError {
+    /// No seat to perform the operation on.
+    ///
+    /// The clipboard uses the last seat which got input events, so none were
+    /// received yet or the seat is gone.
+    NoSeat,
+    /// The client doesn't have keyboard focus on the seat.
+    NoFocus,
+    /// The selection has no content.
+    EmptySelection,
+    /// The selection doesn't offer the requested or any supported mime type.
+    UnsupportedMime,
+    /// The selection offer couldn't be read from yet.
+    OfferNotReady,
+    /// The clipboard thread is gone.
+    WorkerDead,
+    /// The compositor doesn't support the protocol for the requested
+    /// selection.
+    ProtocolUnavailable,
+    /// The operation didn't finish in time.
+    Timeout,
+    /// The operation was cancelled.
+    Cancelled,
+    /// The clipboard history has no entry with the given id.
+    UnknownHistoryEntry,
+    /// The compositor didn't take the stored selection.
+    ///
+    /// The selection set with an outdated input serial is ignored, for
+    /// example when the focus moved on in the meantime.
+    Rejected,
+    /// I/O error while transferring or processing the data.
+    Io(io::Error),
+}
+
+impl fmt::Display for ClipboardError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoSeat => f.write_str("no seat to access the clipboard on"),
+            Self::NoFocus => f.write_str("client doesn't have focus"),
+            Self::EmptySelection => f.write_str("selection is empty"),
+            Self::UnsupportedMime => f.write_str("supported mime-type is not found"),
+            Self::OfferNotReady => f.write_str("offer is not ready yet"),
+            Self::WorkerDead => f.write_str("clipboard is dead"),
+            Self::ProtocolUnavailable => f.write_str("requested selection is not supported"),
+            Self::Timeout => f.write_str("clipboard operation timed out"),
+            Self::Cancelled => f.write_str("clipboard operation was cancelled"),
+            Self::UnknownHistoryEntry => f.write_str("clipboard history entry is not found"),
+            Self::Rejected => f.write_str("selection was rejected by the compositor"),
+            Self::Io(err) => write!(f, "clipboard i/o error: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for ClipboardError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ClipboardError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<ClipboardError> for io::Error {
+    fn from(err: ClipboardError) -> Self {
+        let kind = match err {
+            ClipboardError::Io(err) => return err,
+            ClipboardError::UnsupportedMime | ClipboardError::UnknownHistoryEntry => {
+                io::ErrorKind::NotFound
+            },
+            ClipboardError::Timeout => io::ErrorKind::TimedOut,
+            ClipboardError::Cancelled => io::ErrorKind::Interrupted,
+            ClipboardError::ProtocolUnavailable => io::ErrorKind::Unsupported,
+            _ => io::ErrorKind::Other,
+        };
+        io::Error::new(kind, err)
+    }
+}
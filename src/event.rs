@@ -0,0 +1,51 @@
+use std::sync::mpsc::{Receiver, TryRecvError};
+
+use crate::state::SelectionTarget;
+
+/// Change of the selection content.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SelectionChange {
+    /// The selection which changed.
+    pub target: SelectionTarget,
+    /// The name of the seat the selection changed on, if the compositor
+    /// advertised one.
+    pub seat_name: Option<String>,
+    /// Mime types offered by the new selection content.
+    ///
+    /// Empty when the selection was cleared.
+    pub mime_types: Vec<String>,
+}
+
+/// Selection stored by this clipboard.
+///
+/// Tracks whether the selection is still ours, which stops once another
+/// client takes the selection or it's withdrawn.
+#[derive(Debug)]
+pub struct StoredSelection {
+    target: SelectionTarget,
+    cancelled: Receiver<()>,
+}
+
+impl StoredSelection {
+    pub(crate) fn new(target: SelectionTarget, cancelled: Receiver<()>) -> Self {
+        Self { target, cancelled }
+    }
+
+    /// The selection the content was stored to.
+    pub fn target(&self) -> SelectionTarget {
+        self.target
+    }
+
+    /// Whether the selection was cancelled.
+    ///
+    /// The selection is also considered cancelled once the clipboard is
+    /// dropped.
+    pub fn is_cancelled(&self) -> bool {
+        !matches!(self.cancelled.try_recv(), Err(TryRecvError::Empty))
+    }
+
+    /// Block until the selection is cancelled.
+    pub fn wait_cancelled(&self) {
+        let _ = self.cancelled.recv();
+    }
+}
@@ -0,0 +1,197 @@
+use std::ffi::OsString;
+use std::io::{Error, ErrorKind, Result};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+
+/// URI list mime type.
+pub static URI_LIST_MIME_TYPE: &str = "text/uri-list";
+
+/// GNOME copied files mime type.
+///
+/// Same as URI list, but prefixed with the operation line.
+pub static GNOME_COPIED_FILES_MIME_TYPE: &str = "x-special/gnome-copied-files";
+
+/// KDE cut selection mime type.
+///
+/// Set to `1` when the files were cut.
+pub static KDE_CUT_SELECTION_MIME_TYPE: &str = "application/x-kde-cutselection";
+
+/// Operation performed on the files put on the clipboard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileOperation {
+    /// The files were copied.
+    Copy,
+    /// The files were cut, so the pasting client should move them.
+    Cut,
+}
+
+impl FileOperation {
+    /// Operation line for the GNOME copied files list.
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            Self::Copy => "copy",
+            Self::Cut => "cut",
+        }
+    }
+}
+
+/// Encode absolute `paths` into the URI list.
+pub fn encode_uri_list<P: AsRef<Path>>(paths: &[P]) -> Result<String> {
+    let mut uri_list = String::new();
+    for path in paths {
+        let path = path.as_ref();
+        if !path.is_absolute() {
+            return Err(Error::new(ErrorKind::InvalidInput, "file path is not absolute"));
+        }
+
+        uri_list.push_str("file://");
+        percent_encode(path.as_os_str().as_bytes(), &mut uri_list);
+        // URI list requires CRLF line endings according to RFC-2483.
+        uri_list.push_str("\r\n");
+    }
+
+    Ok(uri_list)
+}
+
+/// Decode local file paths from the URI list.
+///
+/// Fails when the list has URIs which don't point to a local file.
+pub fn decode_uri_list(uri_list: &str) -> Result<Vec<PathBuf>> {
+    uri_list
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(decode_file_uri)
+        .collect()
+}
+
+/// Decode local file path from the `file` URI.
+fn decode_file_uri(uri: &str) -> Result<PathBuf> {
+    let path = match uri.get(..5) {
+        Some(scheme) if scheme.eq_ignore_ascii_case("file:") => &uri[5..],
+        _ => return Err(Error::new(ErrorKind::InvalidData, "uri is not a file uri")),
+    };
+
+    let path = match path.strip_prefix("//") {
+        Some(authority) => {
+            let host_end = authority.find('/').unwrap_or(authority.len());
+            if !is_local_host(&authority[..host_end]) {
+                return Err(Error::new(ErrorKind::InvalidData, "file uri has non-local host"));
+            }
+            &authority[host_end..]
+        },
+        None => path,
+    };
+
+    if !path.starts_with('/') {
+        return Err(Error::new(ErrorKind::InvalidData, "file uri path is not absolute"));
+    }
+
+    Ok(PathBuf::from(OsString::from_vec(percent_decode(path)?)))
+}
+
+/// Whether the `host` of the file URI is the local machine.
+fn is_local_host(host: &str) -> bool {
+    if host.is_empty() || host.eq_ignore_ascii_case("localhost") {
+        return true;
+    }
+
+    let mut hostname = [0u8; 256];
+    let result = unsafe { libc::gethostname(hostname.as_mut_ptr().cast(), hostname.len()) };
+    if result != 0 {
+        return false;
+    }
+
+    let len = hostname.iter().position(|&byte| byte == 0).unwrap_or(hostname.len());
+    host.as_bytes().eq_ignore_ascii_case(&hostname[..len])
+}
+
+/// Percent encode all the bytes of the `path` except unreserved ones.
+fn percent_encode(path: &[u8], encoded: &mut String) {
+    for &byte in path {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                encoded.push(byte as char)
+            },
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+}
+
+/// Decode percent encoded bytes in the `path`.
+fn percent_decode(path: &str) -> Result<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(path.len());
+    let mut bytes = path.bytes();
+    while let Some(byte) = bytes.next() {
+        if byte != b'%' {
+            decoded.push(byte);
+            continue;
+        }
+
+        let hex = [bytes.next(), bytes.next()];
+        let byte = match hex {
+            [Some(high), Some(low)] if high.is_ascii_hexdigit() && low.is_ascii_hexdigit() => {
+                std::str::from_utf8(&[high, low])
+                    .ok()
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            },
+            _ => None,
+        };
+        decoded.push(
+            byte.ok_or_else(|| Error::new(ErrorKind::InvalidData, "invalid percent encoding"))?,
+        );
+    }
+
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsStr;
+
+    use super::*;
+
+    #[test]
+    fn uri_list_round_trips() {
+        let paths = [Path::new("/tmp/plain"), Path::new("/tmp/with space/100%/ü#?")];
+        let uri_list = encode_uri_list(&paths).unwrap();
+        assert_eq!(
+            uri_list,
+            "file:///tmp/plain\r\nfile:///tmp/with%20space/100%25/%C3%BC%23%3F\r\n"
+        );
+        assert_eq!(decode_uri_list(&uri_list).unwrap(), paths);
+
+        let path = Path::new(OsStr::from_bytes(b"/tmp/\xff"));
+        let uri_list = encode_uri_list(&[path]).unwrap();
+        assert_eq!(decode_uri_list(&uri_list).unwrap(), [path]);
+
+        assert!(encode_uri_list(&["relative"]).is_err());
+    }
+
+    #[test]
+    fn uri_list_skips_comments() {
+        let uri_list = "# comment\r\n\r\nfile:///first\nFILE:/second\r\n#file:///third";
+        let paths = decode_uri_list(uri_list).unwrap();
+        assert_eq!(paths, [Path::new("/first"), Path::new("/second")]);
+    }
+
+    #[test]
+    fn file_uri_must_be_local() {
+        assert_eq!(decode_file_uri("file://localhost/tmp").unwrap(), Path::new("/tmp"));
+        assert_eq!(decode_file_uri("file://LOCALHOST/tmp").unwrap(), Path::new("/tmp"));
+        assert_eq!(decode_file_uri("file:///tmp").unwrap(), Path::new("/tmp"));
+
+        assert!(decode_file_uri("file://example.invalid/tmp").is_err());
+        assert!(decode_file_uri("https://localhost/tmp").is_err());
+        assert!(decode_file_uri("file:tmp").is_err());
+        assert!(decode_uri_list("file:///tmp\r\nfile://example.invalid/tmp").is_err());
+    }
+
+    #[test]
+    fn invalid_percent_encoding_fails() {
+        assert_eq!(percent_decode("/a%2Fb%c3%bc").unwrap(), b"/a/b\xc3\xbc");
+        for path in ["/%", "/%4", "/%zz", "/%+1", "/%\u{e9}"] {
+            assert!(percent_decode(path).is_err(), "{path}");
+        }
+    }
+}
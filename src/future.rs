@@ -0,0 +1,176 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use crate::error::{ClipboardError, Result};
+use crate::load::LoadCanceller;
+
+/// Create a channel to deliver the reply of the load to a future.
+///
+/// The load is cancelled with the `canceller` when the future is dropped
+/// before it resolved.
+pub fn channel<T>(canceller: LoadCanceller) -> (ReplySender<T>, ReplyFuture<T>) {
+    let shared = Arc::new(Mutex::new(Shared { reply: None, waker: None, closed: false }));
+    let future = ReplyFuture { shared: shared.clone(), canceller, finished: false };
+    (ReplySender { shared }, future)
+}
+
+/// State shared between the sender and the future.
+struct Shared<T> {
+    reply: Option<Result<T>>,
+    waker: Option<Waker>,
+    /// The sender is gone.
+    closed: bool,
+}
+
+/// Sending half of the reply channel.
+pub struct ReplySender<T> {
+    shared: Arc<Mutex<Shared<T>>>,
+}
+
+impl<T> ReplySender<T> {
+    /// Send the reply, waking up the future.
+    pub fn send(self, reply: Result<T>) {
+        self.shared.lock().unwrap().reply = Some(reply);
+    }
+}
+
+impl<T> Drop for ReplySender<T> {
+    fn drop(&mut self) {
+        let mut shared = self.shared.lock().unwrap();
+        shared.closed = true;
+        if let Some(waker) = shared.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// Future resolving to the clipboard thread reply.
+///
+/// Resolves to an error when the clipboard thread is gone without replying.
+/// Dropping the future cancels the load if it hasn't resolved yet.
+pub struct ReplyFuture<T> {
+    shared: Arc<Mutex<Shared<T>>>,
+    canceller: LoadCanceller,
+    finished: bool,
+}
+
+impl<T> Future for ReplyFuture<T> {
+    type Output = Result<T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut shared = self.shared.lock().unwrap();
+        let reply = if let Some(reply) = shared.reply.take() {
+            reply
+        } else if shared.closed {
+            Err(ClipboardError::WorkerDead)
+        } else {
+            shared.waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        };
+
+        drop(shared);
+        self.finished = true;
+        Poll::Ready(reply)
+    }
+}
+
+impl<T> Drop for ReplyFuture<T> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.canceller.cancel();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::task::Wake;
+    use std::thread::{self, Thread};
+
+    use sctk::reexports::calloop;
+
+    use super::*;
+    use crate::state::SelectionTarget;
+    use crate::test_compositor::{DataControlProtocol, TestCompositor, connect};
+
+    /// Wake up the thread blocked on the future.
+    struct ThreadWaker(Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    /// Minimal local executor driving the future on the current thread.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = std::pin::pin!(future);
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    /// Canceller of the load not known to any clipboard thread.
+    fn canceller() -> LoadCanceller {
+        let (request_sender, _) = calloop::channel::channel();
+        LoadCanceller::new(0, request_sender)
+    }
+
+    #[test]
+    fn resolves_to_reply_from_another_thread() {
+        let (sender, future) = channel(canceller());
+        let worker = thread::spawn(move || sender.send(Ok(vec![1, 2, 3])));
+        assert_eq!(block_on(future).unwrap(), [1, 2, 3]);
+        worker.join().unwrap();
+    }
+
+    #[test]
+    fn resolves_to_error_reply() {
+        let (sender, future) = channel::<Vec<u8>>(canceller());
+        sender.send(Err(ClipboardError::EmptySelection));
+        assert!(matches!(block_on(future), Err(ClipboardError::EmptySelection)));
+    }
+
+    #[test]
+    fn resolves_to_error_when_sender_is_dropped() {
+        let (sender, future) = channel::<Vec<u8>>(canceller());
+        let worker = thread::spawn(move || drop(sender));
+        assert!(matches!(block_on(future), Err(ClipboardError::WorkerDead)));
+        worker.join().unwrap();
+    }
+
+    #[test]
+    fn loads_from_compositor() {
+        let (compositor, _connection, clipboard) = connect(DataControlProtocol::ext());
+        compositor.set_selection(SelectionTarget::Clipboard, &[("text/plain", b"async")]);
+        TestCompositor::wait_for(|| clipboard.load().ok().as_deref() == Some("async"));
+
+        assert_eq!(block_on(clipboard.load_async()).unwrap(), "async");
+    }
+
+    #[test]
+    fn dropped_load_is_cancelled() {
+        let (compositor, _connection, clipboard) = connect(DataControlProtocol::ext());
+        compositor.set_stalled_selection(SelectionTarget::Clipboard, &["text/plain"]);
+        TestCompositor::wait_for(|| {
+            clipboard.available_mime_types().is_ok_and(|mime_types| !mime_types.is_empty())
+        });
+
+        let load = clipboard.load_async();
+        TestCompositor::wait_for(|| compositor.write_stalled(b"partial").is_some());
+        drop(load);
+
+        // The pipe is closed once the load is cancelled.
+        TestCompositor::wait_for(|| {
+            compositor.write_stalled(b"late").is_some_and(|res| res.is_err())
+        });
+    }
+}
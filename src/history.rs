@@ -0,0 +1,300 @@
+use std::collections::VecDeque;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::time::SystemTime;
+
+use crate::content::ClipboardContent;
+use crate::error::{ClipboardError, Result};
+
+/// Identifier of the clipboard history entry.
+pub type HistoryId = u64;
+
+/// Entry of the clipboard history.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryEntry {
+    /// Identifier of the entry, which stays the same when the content is
+    /// copied again.
+    pub id: HistoryId,
+    /// When the content was copied the last time.
+    pub timestamp: SystemTime,
+    /// Mime types the content was offered in.
+    pub mime_types: Vec<String>,
+    /// Size of the content in bytes, summed over all of its mime types.
+    pub size: usize,
+    /// Whether the entry is kept regardless of the history capacity.
+    pub pinned: bool,
+}
+
+/// History of the clipboard content, the most recent first.
+#[derive(Debug)]
+pub struct History {
+    /// Number of entries kept, not counting the pinned ones.
+    capacity: usize,
+    next_id: HistoryId,
+    entries: VecDeque<StoredEntry>,
+}
+
+#[derive(Debug)]
+struct StoredEntry {
+    entry: HistoryEntry,
+    hash: u64,
+    data: Vec<(String, Vec<u8>)>,
+}
+
+impl History {
+    pub fn new(capacity: usize) -> Self {
+        Self { capacity, next_id: 0, entries: VecDeque::new() }
+    }
+
+    /// Change the number of kept entries, dropping the oldest ones.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.evict();
+    }
+
+    /// Add the copied content, moving the entry with the same content to the
+    /// front instead.
+    pub fn push(&mut self, data: Vec<(String, Vec<u8>)>) {
+        if data.is_empty() {
+            return;
+        }
+
+        // The hash only rules out the different content.
+        let hash = content_hash(&data);
+        let sorted_data = sorted(&data);
+        let same = self
+            .entries
+            .iter()
+            .position(|stored| stored.hash == hash && sorted(&stored.data) == sorted_data);
+        let stored = match same.and_then(|index| self.entries.remove(index)) {
+            Some(mut stored) => {
+                stored.entry.timestamp = SystemTime::now();
+                stored
+            },
+            None => {
+                let entry = HistoryEntry {
+                    id: self.next_id,
+                    timestamp: SystemTime::now(),
+                    mime_types: data.iter().map(|(mime, _)| mime.clone()).collect(),
+                    size: data.iter().map(|(_, data)| data.len()).sum(),
+                    pinned: false,
+                };
+                self.next_id += 1;
+                StoredEntry { entry, hash, data }
+            },
+        };
+
+        self.entries.push_front(stored);
+        self.evict();
+    }
+
+    /// The entries, the most recent first.
+    pub fn entries(&self) -> Vec<HistoryEntry> {
+        self.entries.iter().map(|stored| stored.entry.clone()).collect()
+    }
+
+    /// Data of the entry in the given mime type.
+    pub fn load(&self, id: HistoryId, mime_type: &str) -> Result<Vec<u8>> {
+        let stored = self.get(id)?;
+        let (_, data) = stored
+            .data
+            .iter()
+            .find(|(mime, _)| mime == mime_type)
+            .ok_or(ClipboardError::UnsupportedMime)?;
+        Ok(data.clone())
+    }
+
+    /// Content of the entry in all of its mime types.
+    pub fn content(&self, id: HistoryId) -> Result<ClipboardContent> {
+        let stored = self.get(id)?;
+        let content = stored.data.iter().fold(ClipboardContent::new(), |content, (mime, data)| {
+            content.with_mime(mime.clone(), data.clone())
+        });
+        Ok(content)
+    }
+
+    /// Pin or unpin the entry.
+    pub fn set_pinned(&mut self, id: HistoryId, pinned: bool) -> Result<()> {
+        let index = self.position(id)?;
+        self.entries[index].entry.pinned = pinned;
+        self.evict();
+        Ok(())
+    }
+
+    /// Remove the entry, pinned or not.
+    pub fn remove(&mut self, id: HistoryId) -> Result<()> {
+        let index = self.position(id)?;
+        self.entries.remove(index);
+        Ok(())
+    }
+
+    fn get(&self, id: HistoryId) -> Result<&StoredEntry> {
+        Ok(&self.entries[self.position(id)?])
+    }
+
+    fn position(&self, id: HistoryId) -> Result<usize> {
+        self.entries
+            .iter()
+            .position(|stored| stored.entry.id == id)
+            .ok_or(ClipboardError::UnknownHistoryEntry)
+    }
+
+    /// Drop the oldest unpinned entries exceeding the capacity.
+    fn evict(&mut self) {
+        let mut unpinned = 0;
+        self.entries.retain(|stored| {
+            if stored.entry.pinned {
+                return true;
+            }
+
+            unpinned += 1;
+            unpinned <= self.capacity
+        });
+    }
+}
+
+/// Hash of the content, not depending on the order of its mime types.
+fn content_hash(data: &[(String, Vec<u8>)]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    sorted(data).hash(&mut hasher);
+    hasher.finish()
+}
+
+/// The content sorted by its mime types.
+fn sorted(data: &[(String, Vec<u8>)]) -> Vec<&(String, Vec<u8>)> {
+    let mut data: Vec<_> = data.iter().collect();
+    data.sort_unstable_by(|(first, _), (second, _)| first.cmp(second));
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::mime::PASSWORD_MANAGER_HINT_MIME_TYPE;
+    use crate::state::SelectionTarget;
+    use crate::test_compositor::{DataControlProtocol, TestCompositor, connect};
+
+    fn text(text: &str) -> Vec<(String, Vec<u8>)> {
+        vec![(String::from("text/plain"), text.into())]
+    }
+
+    #[test]
+    fn same_content_is_moved_to_front() {
+        let mut history = History::new(10);
+        history.push(text("first"));
+        history.push(text("second"));
+        history.push(text("first"));
+
+        let entries = history.entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].id, 0);
+        assert_eq!(history.load(entries[0].id, "text/plain").unwrap(), b"first");
+        assert_eq!(entries[0].size, 5);
+    }
+
+    #[test]
+    fn same_hash_is_not_same_content() {
+        let mut history = History::new(10);
+        history.push(text("first"));
+        history.entries[0].hash = content_hash(&text("second"));
+        history.push(text("second"));
+        assert_eq!(history.entries().len(), 2);
+
+        let mut reordered = text("first");
+        reordered.insert(0, (String::from("text/html"), b"<b>first</b>".to_vec()));
+        history.push(reordered.clone());
+        reordered.reverse();
+        history.push(reordered);
+        assert_eq!(history.entries().len(), 3);
+    }
+
+    #[test]
+    fn pinned_entries_outlive_capacity() {
+        let mut history = History::new(1);
+        history.push(text("pinned"));
+        history.set_pinned(0, true).unwrap();
+        history.push(text("second"));
+        history.push(text("third"));
+
+        let ids: Vec<_> = history.entries().iter().map(|entry| entry.id).collect();
+        assert_eq!(ids, [2, 0]);
+
+        history.remove(0).unwrap();
+        assert!(matches!(history.remove(0), Err(ClipboardError::UnknownHistoryEntry)));
+        assert_eq!(history.entries().len(), 1);
+    }
+
+    #[test]
+    fn history_follows_clipboard() {
+        let (compositor, _connection, clipboard) = connect(DataControlProtocol::ext());
+        clipboard.enable_history(10).unwrap();
+        let history_len =
+            |len| TestCompositor::wait_for(|| clipboard.history().unwrap().len() == len);
+
+        compositor.set_selection(SelectionTarget::Clipboard, &[("text/plain", b"first")]);
+        history_len(1);
+        let first = clipboard.history().unwrap()[0].id;
+
+        // Secrets are skipped, while our own content is kept.
+        let secret: [(&str, &[u8]); 2] =
+            [("text/plain", b"secret"), (PASSWORD_MANAGER_HINT_MIME_TYPE, b"secret")];
+        compositor.set_selection(SelectionTarget::Clipboard, &secret);
+        TestCompositor::wait_for(|| clipboard.load().ok().as_deref() == Some("secret"));
+        clipboard.store_sensitive("secret");
+        clipboard.store("ours");
+        history_len(2);
+
+        compositor.set_selection(SelectionTarget::Clipboard, &[("text/plain", b"first")]);
+        TestCompositor::wait_for(|| clipboard.history().unwrap()[0].id == first);
+
+        let entries = clipboard.history().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(clipboard.load_history(entries[1].id, "text/plain").unwrap(), b"ours");
+
+        clipboard.restore_history(entries[1].id).unwrap();
+        TestCompositor::wait_for(|| {
+            compositor.client_selection(SelectionTarget::Clipboard).is_some()
+        });
+        let text = compositor.read_selection(SelectionTarget::Clipboard, "text/plain");
+        assert_eq!(text, b"ours");
+
+        clipboard.disable_history().unwrap();
+        assert!(clipboard.history().unwrap().is_empty());
+    }
+
+    #[test]
+    fn content_replacing_ours_is_recorded() {
+        let (compositor, _connection, clipboard) = connect(DataControlProtocol::ext());
+        clipboard.enable_history(10).unwrap();
+        clipboard.store("ours");
+        TestCompositor::wait_for(|| clipboard.owns_selection());
+
+        // Our source is still alive when the new selection is announced.
+        compositor.set_late_cancel(true);
+        compositor.set_selection(SelectionTarget::Clipboard, &[("text/plain", b"external")]);
+        TestCompositor::wait_for(|| clipboard.history().unwrap().len() == 2);
+        assert!(!clipboard.owns_selection());
+
+        let entry = clipboard.history().unwrap()[0].id;
+        assert_eq!(clipboard.load_history(entry, "text/plain").unwrap(), b"external");
+    }
+
+    #[test]
+    fn own_streams_are_not_recorded() {
+        let (_compositor, _connection, clipboard) = connect(DataControlProtocol::ext());
+        clipboard.enable_history(10).unwrap();
+
+        let content = ClipboardContent::new()
+            .with_provider(["text/plain"], |_: &str| b"lazy".to_vec())
+            .with_reader("text/csv", Box::new(Cursor::new(b"a,b".to_vec())));
+        clipboard.try_store_content(SelectionTarget::Clipboard, content).unwrap();
+        TestCompositor::wait_for(|| clipboard.history().unwrap().len() == 1);
+
+        let entry = &clipboard.history().unwrap()[0];
+        assert_eq!(entry.mime_types, ["text/plain"]);
+        assert_eq!(clipboard.load_history(entry.id, "text/plain").unwrap(), b"lazy");
+        assert_eq!(clipboard.load_mime("text/csv").unwrap(), b"a,b");
+    }
+}
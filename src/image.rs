@@ -0,0 +1,146 @@
+use std::io::{Cursor, Error, ErrorKind, Result};
+
+use ::image::ExtendedColorType;
+
+/// Decoded image with RGBA pixels.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageData {
+    /// Width of the image in pixels.
+    pub width: u32,
+    /// Height of the image in pixels.
+    pub height: u32,
+    /// Pixels in RGBA order, 4 bytes per pixel, row by row.
+    pub rgba: Vec<u8>,
+}
+
+impl ImageData {
+    /// Decode image `data` of the given `format` into RGBA pixels.
+    pub(crate) fn decode(data: &[u8], format: ImageFormat) -> Result<Self> {
+        let image = ::image::load_from_memory_with_format(data, format.into())
+            .map_err(|err| Error::new(ErrorKind::InvalidData, err))?
+            .into_rgba8();
+
+        Ok(Self { width: image.width(), height: image.height(), rgba: image.into_raw() })
+    }
+
+    /// Encode the image into the given `format`.
+    pub(crate) fn encode(&self, format: ImageFormat) -> Result<Vec<u8>> {
+        if self.rgba.len() as u64 != u64::from(self.width) * u64::from(self.height) * 4 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "pixel buffer doesn't match image size",
+            ));
+        }
+
+        let mut data = Cursor::new(Vec::new());
+        let result = match format {
+            // JPEG doesn't support alpha channel.
+            ImageFormat::Jpeg => {
+                let rgb: Vec<u8> =
+                    self.rgba.chunks_exact(4).flat_map(|pixel| &pixel[..3]).copied().collect();
+                ::image::write_buffer_with_format(
+                    &mut data,
+                    &rgb,
+                    self.width,
+                    self.height,
+                    ExtendedColorType::Rgb8,
+                    format.into(),
+                )
+            },
+            ImageFormat::Png | ImageFormat::Bmp => ::image::write_buffer_with_format(
+                &mut data,
+                &self.rgba,
+                self.width,
+                self.height,
+                ExtendedColorType::Rgba8,
+                format.into(),
+            ),
+        };
+
+        result.map_err(Error::other)?;
+        Ok(data.into_inner())
+    }
+}
+
+/// Image format supported by clipboard.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum ImageFormat {
+    /// image/png mime type.
+    ///
+    /// The format used by most clients and preferred when loading.
+    Png,
+    /// image/bmp mime type.
+    Bmp,
+    /// image/jpeg mime type.
+    ///
+    /// Lossy format without alpha channel.
+    Jpeg,
+}
+
+impl ImageFormat {
+    /// Mime type of the format.
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            Self::Png => "image/png",
+            Self::Bmp => "image/bmp",
+            Self::Jpeg => "image/jpeg",
+        }
+    }
+
+    /// Find the best image format among the `offered_mime_types`.
+    ///
+    /// Lossless formats are preferred over lossy ones.
+    pub(crate) fn find_best(offered_mime_types: &[String]) -> Option<Self> {
+        [Self::Png, Self::Bmp, Self::Jpeg]
+            .into_iter()
+            .find(|format| offered_mime_types.iter().any(|mime| mime == format.mime_type()))
+    }
+}
+
+impl From<ImageFormat> for ::image::ImageFormat {
+    fn from(format: ImageFormat) -> Self {
+        match format {
+            ImageFormat::Png => Self::Png,
+            ImageFormat::Bmp => Self::Bmp,
+            ImageFormat::Jpeg => Self::Jpeg,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image() -> ImageData {
+        let pixels: [[u8; 4]; 4] =
+            [[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 255], [255, 255, 255, 255]];
+        ImageData { width: 2, height: 2, rgba: pixels.concat() }
+    }
+
+    #[test]
+    fn image_round_trips() {
+        let image = image();
+        for format in [ImageFormat::Png, ImageFormat::Bmp] {
+            let data = image.encode(format).unwrap();
+            assert_eq!(ImageData::decode(&data, format).unwrap(), image, "{format:?}");
+        }
+
+        let data = image.encode(ImageFormat::Jpeg).unwrap();
+        let decoded = ImageData::decode(&data, ImageFormat::Jpeg).unwrap();
+        assert_eq!((decoded.width, decoded.height), (image.width, image.height));
+    }
+
+    #[test]
+    fn best_format_is_lossless() {
+        let offered = ["image/jpeg", "image/bmp", "text/plain"].map(String::from);
+        assert_eq!(ImageFormat::find_best(&offered), Some(ImageFormat::Bmp));
+        assert_eq!(ImageFormat::find_best(&offered[..1]), Some(ImageFormat::Jpeg));
+        assert_eq!(ImageFormat::find_best(&offered[2..]), None);
+    }
+
+    #[test]
+    fn pixels_must_match_size() {
+        let image = ImageData { width: 3, ..image() };
+        assert!(image.encode(ImageFormat::Png).is_err());
+    }
+}
@@ -5,21 +5,54 @@
 
 #![deny(clippy::all, clippy::if_not_else, clippy::enum_glob_use)]
 use std::ffi::c_void;
-use std::io::Result;
+use std::io::{Error, ErrorKind, Read};
+use std::os::unix::io::AsFd;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::mpsc::{self, Receiver};
+use std::time::Duration;
 
 use sctk::reexports::calloop::channel::{self, Sender};
 use sctk::reexports::client::Connection;
 use sctk::reexports::client::backend::Backend;
 
+mod content;
+mod data_control;
+mod error;
+mod event;
+mod files;
+#[cfg(feature = "async")]
+mod future;
+mod history;
+#[cfg(feature = "image")]
+mod image;
+mod load;
+mod manager;
 mod mime;
 mod state;
+#[cfg(test)]
+mod test_compositor;
 mod worker;
 
+#[cfg(feature = "image")]
+pub use crate::image::{ImageData, ImageFormat};
+pub use content::{ClipboardContent, DataProvider, HtmlContent, StreamProvider};
+pub use error::{ClipboardError, Result};
+pub use event::{SelectionChange, StoredSelection};
+pub use files::FileOperation;
+pub use history::{HistoryEntry, HistoryId};
+pub use load::{LoadCanceller, LoadHandle};
+pub use state::{ClipboardBackend, SelectionTarget};
+
+use state::LoadRequest;
+
 /// Access to a Wayland clipboard.
+///
+/// The clipboard could be shared between threads, with loads from different
+/// threads getting their own replies.
 pub struct Clipboard {
     request_sender: Sender<worker::Command>,
-    request_receiver: Receiver<Result<String>>,
+    next_load_id: AtomicU64,
     clipboard_thread: Option<std::thread::JoinHandle<()>>,
 }
 
@@ -35,62 +68,521 @@ impl Clipboard {
         let backend = unsafe { Backend::from_foreign_display(display.cast()) };
         let connection = Connection::from_backend(backend);
 
-        // Create channel to send data to clipboard thread.
+        // Create channel to send data to clipboard thread. Each request carries
+        // its own reply channel.
         let (request_sender, rx_chan) = channel::channel();
-        // Create channel to get data from the clipboard thread.
-        let (clipboard_reply_sender, request_receiver) = mpsc::channel();
 
         let name = String::from("smithay-clipboard");
-        let clipboard_thread = worker::spawn(name, connection, rx_chan, clipboard_reply_sender);
+        let clipboard_thread = worker::spawn(name, connection, rx_chan);
 
-        Self { request_receiver, request_sender, clipboard_thread }
+        Self { request_sender, clipboard_thread, next_load_id: AtomicU64::new(0) }
     }
 
     /// Load clipboard data.
     ///
     /// Loads content from a clipboard on a last observed seat.
     pub fn load(&self) -> Result<String> {
-        let _ = self.request_sender.send(worker::Command::Load);
+        self.start_load(SelectionTarget::Clipboard).wait()
+    }
+
+    /// Load clipboard data of the given mime type.
+    ///
+    /// Loads raw content of `mime_type` from a clipboard on a last observed
+    /// seat. The content is returned as is, without any post-processing.
+    pub fn load_mime(&self, mime_type: &str) -> Result<Vec<u8>> {
+        self.start_load_mime(SelectionTarget::Clipboard, mime_type).wait()
+    }
 
-        if let Ok(reply) = self.request_receiver.recv() {
-            reply
+    /// Load clipboard data of the given mime type as a stream.
+    ///
+    /// Returns a reader of raw content of `mime_type` from a clipboard on a
+    /// last observed seat, which is read directly from the client owning the
+    /// clipboard. The reader blocks until the data is available, and reaches
+    /// the end once the owning client finishes sending it.
+    pub fn load_stream(&self, mime_type: &str) -> Result<impl Read + AsFd + Send + use<>> {
+        self.request_with_reply(|reply_tx| {
+            worker::Command::LoadStream(mime_type.to_owned(), reply_tx)
+        })
+    }
+
+    /// Load clipboard HTML.
+    ///
+    /// Loads HTML from a clipboard on a last observed seat, falling back to
+    /// plain text when the clipboard doesn't offer HTML.
+    pub fn load_html(&self) -> Result<HtmlContent> {
+        if self.available_mime_types()?.iter().any(|mime| mime == mime::HTML_MIME_TYPE) {
+            let html = self.load_mime(mime::HTML_MIME_TYPE)?;
+            Ok(HtmlContent::Html(mime::decode_html(html)))
         } else {
-            // The clipboard thread is dead, however we shouldn't crash downstream, so
-            // propogating an error.
-            Err(std::io::Error::other("clipboard is dead."))
+            self.load().map(HtmlContent::Text)
         }
     }
 
+    /// Load clipboard files.
+    ///
+    /// Loads paths of the local files from a clipboard on a last observed seat.
+    /// Fails when the clipboard has files which are not local.
+    pub fn load_files(&self) -> Result<Vec<PathBuf>> {
+        let uri_list = self.load_mime(files::URI_LIST_MIME_TYPE)?;
+        Ok(files::decode_uri_list(&String::from_utf8_lossy(&uri_list))?)
+    }
+
+    /// Load clipboard image.
+    ///
+    /// Loads an image from a clipboard on a last observed seat, picking the
+    /// best image format offered, and decodes it into RGBA pixels.
+    #[cfg(feature = "image")]
+    pub fn load_image(&self) -> Result<ImageData> {
+        let format = ImageFormat::find_best(&self.available_mime_types()?)
+            .ok_or(ClipboardError::UnsupportedMime)?;
+        let data = self.load_mime(format.mime_type())?;
+        Ok(ImageData::decode(&data, format)?)
+    }
+
+    /// The protocol used to access the clipboard.
+    ///
+    /// The data control protocols allow to access the clipboard without
+    /// keyboard focus.
+    pub fn backend(&self) -> Result<ClipboardBackend> {
+        self.request_with_reply(worker::Command::Backend)
+    }
+
+    /// Act as the clipboard manager, keeping the content of other clients
+    /// alive.
+    ///
+    /// Every selection is copied in all the offered mime types as soon as it
+    /// changes. Once the client owning the selection exits or withdraws it,
+    /// the last copy is offered by this clipboard instead. Content marked for
+    /// password managers is never copied, neither is the content whose owner
+    /// fails to send it within 10 seconds.
+    ///
+    /// Requires the data control protocol, see [`Clipboard::backend`].
+    pub fn set_manager_mode(&self, enabled: bool) -> Result<()> {
+        self.request_with_reply(|reply_tx| worker::Command::ManagerMode(enabled, reply_tx))
+    }
+
+    /// Keep the history of the clipboard with up to `capacity` entries.
+    ///
+    /// The content is copied in all of its mime types every time the
+    /// clipboard changes, with the same content kept only once. Entries of
+    /// password managers and sensitive content are left out. Pinned entries
+    /// aren't counted towards the capacity.
+    ///
+    /// Content stored by this clipboard is kept without the data streamed to
+    /// the pasting clients, see [`ClipboardContent::with_stream_provider`],
+    /// while the data of [`ClipboardContent::with_provider`] is produced
+    /// right away.
+    ///
+    /// The changes are only seen with focus, unless the data control protocol
+    /// is available, see [`Clipboard::backend`].
+    pub fn enable_history(&self, capacity: usize) -> Result<()> {
+        self.request_with_reply(|reply_tx| {
+            worker::Command::HistoryCapacity(Some(capacity), reply_tx)
+        })
+    }
+
+    /// Stop keeping the clipboard history, dropping its entries.
+    pub fn disable_history(&self) -> Result<()> {
+        self.request_with_reply(|reply_tx| worker::Command::HistoryCapacity(None, reply_tx))
+    }
+
+    /// Entries of the clipboard history, the most recent first.
+    pub fn history(&self) -> Result<Vec<HistoryEntry>> {
+        self.request_with_reply(worker::Command::History)
+    }
+
+    /// Load the content of the history entry in the given mime type.
+    pub fn load_history(&self, id: HistoryId, mime_type: &str) -> Result<Vec<u8>> {
+        let mime_type = mime_type.to_string();
+        self.request_with_reply(|reply_tx| worker::Command::LoadHistory(id, mime_type, reply_tx))
+    }
+
+    /// Pin the history entry, keeping it regardless of the history capacity,
+    /// or unpin it.
+    pub fn pin_history(&self, id: HistoryId, pinned: bool) -> Result<()> {
+        self.request_with_reply(|reply_tx| worker::Command::PinHistory(id, pinned, reply_tx))
+    }
+
+    /// Remove the history entry.
+    pub fn remove_history(&self, id: HistoryId) -> Result<()> {
+        self.request_with_reply(|reply_tx| worker::Command::RemoveHistory(id, reply_tx))
+    }
+
+    /// Store the content of the history entry to the clipboard again.
+    pub fn restore_history(&self, id: HistoryId) -> Result<()> {
+        self.request_with_reply(|reply_tx| worker::Command::RestoreHistory(id, reply_tx))
+    }
+
+    /// Mime types offered by a clipboard.
+    ///
+    /// Lists mime types of the clipboard content on a last observed seat
+    /// without transferring any data.
+    pub fn available_mime_types(&self) -> Result<Vec<String>> {
+        self.request_with_reply(worker::Command::MimeTypes)
+    }
+
     /// Store to a clipboard.
     ///
     /// Stores to a clipboard on a last observed seat.
     pub fn store<T: Into<String>>(&self, text: T) {
-        let request = worker::Command::Store(text.into());
+        self.store_content(ClipboardContent::new().with_text(text));
+    }
+
+    /// Store sensitive text, like a password, to a clipboard.
+    ///
+    /// See [`ClipboardContent::sensitive`] for details.
+    pub fn store_sensitive<T: Into<String>>(&self, text: T) {
+        self.store_content(ClipboardContent::new().with_text(text).sensitive());
+    }
+
+    /// Store sensitive text, like a one-time code, to a clipboard for a
+    /// single paste.
+    ///
+    /// The text is hidden from clipboard managers, so their copies don't use
+    /// up the paste. See [`ClipboardContent::max_transfers`] and
+    /// [`ClipboardContent::sensitive`] for details.
+    pub fn store_once<T: Into<String>>(&self, text: T) {
+        let content = ClipboardContent::new().with_text(text).sensitive().max_transfers(1);
+        self.store_content(content);
+    }
+
+    /// Store to a clipboard, reporting whether it was stored.
+    ///
+    /// Unlike [`Self::store`], fails when there's no seat, the client doesn't
+    /// have focus, or the compositor doesn't support the clipboard. Waits for
+    /// the compositor to take the selection, failing with
+    /// [`ClipboardError::Rejected`] when it doesn't. The returned
+    /// [`StoredSelection`] tells when the selection is cancelled, meaning
+    /// another client took it.
+    pub fn try_store<T: Into<String>>(&self, text: T) -> Result<StoredSelection> {
+        self.try_store_content(SelectionTarget::Clipboard, ClipboardContent::new().with_text(text))
+    }
+
+    /// Whether the clipboard holds the content stored by this clipboard.
+    ///
+    /// Becomes `false` once another client takes the clipboard.
+    pub fn owns_selection(&self) -> bool {
+        self.owns(SelectionTarget::Clipboard)
+    }
+
+    /// Store to a clipboard for the given duration.
+    ///
+    /// The clipboard is cleared once the `expiry` passes, unless its content
+    /// was replaced in the meantime.
+    pub fn store_with_expiry<T: Into<String>>(&self, text: T, expiry: Duration) {
+        let content = ClipboardContent::new().with_text(text);
+        let request = worker::Command::StoreWithExpiry(SelectionTarget::Clipboard, content, expiry);
         let _ = self.request_sender.send(request);
     }
 
+    /// Clear the clipboard.
+    ///
+    /// Withdraws the content stored by this clipboard and frees it. The
+    /// content of another client owning the clipboard is left intact.
+    pub fn clear(&self) {
+        let _ = self.request_sender.send(worker::Command::Clear(SelectionTarget::Clipboard));
+    }
+
+    /// Store HTML to a clipboard.
+    ///
+    /// Stores `html` along with its plain text `alt_text` representation to a
+    /// clipboard on a last observed seat.
+    pub fn store_html<H: Into<String>, T: Into<String>>(&self, html: H, alt_text: T) {
+        self.store_content(ClipboardContent::new().with_html(html, alt_text));
+    }
+
+    /// Store files to a clipboard.
+    ///
+    /// Stores absolute paths of the copied files to a clipboard on a last
+    /// observed seat. Use [`ClipboardContent::with_files`] to cut the files
+    /// instead.
+    pub fn store_files<P: AsRef<Path>>(&self, paths: &[P]) -> Result<()> {
+        self.store_content(ClipboardContent::new().with_files(paths, FileOperation::Copy)?);
+        Ok(())
+    }
+
+    /// Store data streamed from a reader to a clipboard.
+    ///
+    /// Stores data of the given `mime_type` to a clipboard on a last observed
+    /// seat, streaming it from the `reader` once the pasting client requests
+    /// it. The reader could only be streamed once, use
+    /// [`ClipboardContent::with_stream_provider`] to serve multiple pastes.
+    pub fn store_from_reader<M: Into<String>>(&self, mime_type: M, reader: Box<dyn Read + Send>) {
+        self.store_content(ClipboardContent::new().with_reader(mime_type, reader));
+    }
+
+    /// Store file contents to a clipboard.
+    ///
+    /// Stores data of the given `mime_type` to a clipboard on a last observed
+    /// seat, streaming it from the file at `path` each time the pasting client
+    /// requests it.
+    pub fn store_file<M: Into<String>, P: Into<PathBuf>>(
+        &self,
+        mime_type: M,
+        path: P,
+    ) -> Result<()> {
+        self.store_content(ClipboardContent::new().with_file(mime_type, path)?);
+        Ok(())
+    }
+
+    /// Store image to a clipboard.
+    ///
+    /// Stores `rgba` pixels of the image with the given size encoded as PNG to
+    /// a clipboard on a last observed seat. Use
+    /// [`ClipboardContent::with_image`] to offer the image in other formats
+    /// as well.
+    #[cfg(feature = "image")]
+    pub fn store_image(&self, width: u32, height: u32, rgba: Vec<u8>) -> Result<()> {
+        let image = ImageData { width, height, rgba };
+        self.store_content(ClipboardContent::new().with_image(&image, ImageFormat::Png)?);
+        Ok(())
+    }
+
+    /// Store content to a clipboard.
+    ///
+    /// Stores all the representations of the `content` to a clipboard on a last
+    /// observed seat, serving the one matching the mime type requested by the
+    /// pasting client.
+    pub fn store_content(&self, content: ClipboardContent) {
+        let request = worker::Command::Store(content);
+        let _ = self.request_sender.send(request);
+    }
+
+    /// Store the content to the given selection, reporting whether it was
+    /// stored.
+    ///
+    /// See [`Self::try_store`] for details.
+    pub fn try_store_content(
+        &self,
+        target: SelectionTarget,
+        content: ClipboardContent,
+    ) -> Result<StoredSelection> {
+        self.request_with_reply(|reply_tx| worker::Command::TryStore(target, content, reply_tx))
+    }
+
     /// Load primary clipboard data.
     ///
     /// Loads content from a  primary clipboard on a last observed seat.
     pub fn load_primary(&self) -> Result<String> {
-        let _ = self.request_sender.send(worker::Command::LoadPrimary);
+        self.start_load(SelectionTarget::Primary).wait()
+    }
 
-        if let Ok(reply) = self.request_receiver.recv() {
-            reply
-        } else {
-            // The clipboard thread is dead, however we shouldn't crash downstream, so
-            // propogating an error.
-            Err(std::io::Error::other("clipboard is dead."))
-        }
+    /// Load primary clipboard data of the given mime type.
+    ///
+    /// Loads raw content of `mime_type` from a primary clipboard on a last
+    /// observed seat. The content is returned as is, without any
+    /// post-processing.
+    pub fn load_primary_mime(&self, mime_type: &str) -> Result<Vec<u8>> {
+        self.start_load_mime(SelectionTarget::Primary, mime_type).wait()
+    }
+
+    /// Load primary clipboard data of the given mime type as a stream.
+    ///
+    /// Returns a reader of raw content of `mime_type` from a primary clipboard
+    /// on a last observed seat, which is read directly from the client owning
+    /// the primary clipboard. The reader blocks until the data is available,
+    /// and reaches the end once the owning client finishes sending it.
+    pub fn load_primary_stream(&self, mime_type: &str) -> Result<impl Read + AsFd + Send + use<>> {
+        self.request_with_reply(|reply_tx| {
+            worker::Command::LoadPrimaryStream(mime_type.to_owned(), reply_tx)
+        })
+    }
+
+    /// Mime types offered by a primary clipboard.
+    ///
+    /// Lists mime types of the primary clipboard content on a last observed
+    /// seat without transferring any data.
+    pub fn available_primary_mime_types(&self) -> Result<Vec<String>> {
+        self.request_with_reply(worker::Command::PrimaryMimeTypes)
     }
 
     /// Store to a primary clipboard.
     ///
     /// Stores to a primary clipboard on a last observed seat.
     pub fn store_primary<T: Into<String>>(&self, text: T) {
-        let request = worker::Command::StorePrimary(text.into());
+        self.store_primary_content(ClipboardContent::new().with_text(text));
+    }
+
+    /// Store to a primary clipboard, reporting whether it was stored.
+    ///
+    /// See [`Self::try_store`] for details.
+    pub fn try_store_primary<T: Into<String>>(&self, text: T) -> Result<StoredSelection> {
+        self.try_store_content(SelectionTarget::Primary, ClipboardContent::new().with_text(text))
+    }
+
+    /// Store content to a primary clipboard.
+    ///
+    /// Stores all the representations of the `content` to a primary clipboard
+    /// on a last observed seat, serving the one matching the mime type
+    /// requested by the pasting client.
+    pub fn store_primary_content(&self, content: ClipboardContent) {
+        let request = worker::Command::StorePrimary(content);
+        let _ = self.request_sender.send(request);
+    }
+
+    /// Clear the primary clipboard.
+    ///
+    /// Withdraws the content stored by this clipboard and frees it. The
+    /// content of another client owning the primary clipboard is left intact.
+    pub fn clear_primary(&self) {
+        let _ = self.request_sender.send(worker::Command::Clear(SelectionTarget::Primary));
+    }
+
+    /// Whether the primary clipboard holds the content stored by this
+    /// clipboard.
+    ///
+    /// Becomes `false` once another client takes the primary clipboard.
+    pub fn owns_primary(&self) -> bool {
+        self.owns(SelectionTarget::Primary)
+    }
+
+    /// Load clipboard data asynchronously.
+    ///
+    /// Same as [`Self::load`], but returns a future resolving once the content
+    /// is loaded instead of blocking. The future is not tied to any executor,
+    /// and dropping it cancels the load.
+    #[cfg(feature = "async")]
+    pub fn load_async(&self) -> impl Future<Output = Result<String>> + Send + use<> {
+        let content = self.load_with_reply(SelectionTarget::Clipboard, LoadRequest::Text);
+        async { text_from_utf8(content.await?) }
+    }
+
+    /// Load clipboard data of the given mime type asynchronously.
+    ///
+    /// Same as [`Self::load_mime`], but returns a future resolving once the
+    /// content is loaded instead of blocking. The future is not tied to any
+    /// executor, and dropping it cancels the load.
+    #[cfg(feature = "async")]
+    pub fn load_mime_async(
+        &self,
+        mime_type: &str,
+    ) -> impl Future<Output = Result<Vec<u8>>> + Send + use<> {
+        self.load_with_reply(SelectionTarget::Clipboard, LoadRequest::Mime(mime_type.to_owned()))
+    }
+
+    /// Load primary clipboard data asynchronously.
+    ///
+    /// Same as [`Self::load_primary`], but returns a future resolving once the
+    /// content is loaded instead of blocking. The future is not tied to any
+    /// executor, and dropping it cancels the load.
+    #[cfg(feature = "async")]
+    pub fn load_primary_async(&self) -> impl Future<Output = Result<String>> + Send + use<> {
+        let content = self.load_with_reply(SelectionTarget::Primary, LoadRequest::Text);
+        async { text_from_utf8(content.await?) }
+    }
+
+    /// Load primary clipboard data of the given mime type asynchronously.
+    ///
+    /// Same as [`Self::load_primary_mime`], but returns a future resolving
+    /// once the content is loaded instead of blocking. The future is not tied
+    /// to any executor, and dropping it cancels the load.
+    #[cfg(feature = "async")]
+    pub fn load_primary_mime_async(
+        &self,
+        mime_type: &str,
+    ) -> impl Future<Output = Result<Vec<u8>>> + Send + use<> {
+        self.load_with_reply(SelectionTarget::Primary, LoadRequest::Mime(mime_type.to_owned()))
+    }
+
+    /// Load clipboard data, giving up after the `timeout`.
+    ///
+    /// Same as [`Self::load`], but cancels the load and returns
+    /// [`ClipboardError::Timeout`] when the content wasn't loaded in time.
+    pub fn load_timeout(&self, timeout: Duration) -> Result<String> {
+        self.start_load(SelectionTarget::Clipboard).wait_timeout(timeout)
+    }
+
+    /// Load primary clipboard data, giving up after the `timeout`.
+    ///
+    /// Same as [`Self::load_primary`], but cancels the load and returns
+    /// [`ClipboardError::Timeout`] when the content wasn't loaded in time.
+    pub fn load_primary_timeout(&self, timeout: Duration) -> Result<String> {
+        self.start_load(SelectionTarget::Primary).wait_timeout(timeout)
+    }
+
+    /// Start loading text from the given selection.
+    ///
+    /// Returns a handle to wait for the load to finish or to cancel it.
+    pub fn start_load(&self, target: SelectionTarget) -> LoadHandle<String> {
+        self.start_load_request(target, LoadRequest::Text, text_from_utf8)
+    }
+
+    /// Start loading data of the given mime type from the given selection.
+    ///
+    /// Returns a handle to wait for the load to finish or to cancel it.
+    pub fn start_load_mime(&self, target: SelectionTarget, mime_type: &str) -> LoadHandle<Vec<u8>> {
+        self.start_load_request(target, LoadRequest::Mime(mime_type.to_owned()), Ok)
+    }
+
+    /// Subscribe to selection changes.
+    ///
+    /// The returned receiver gets notified each time the clipboard or the
+    /// primary clipboard changes on any seat, including the changes made by
+    /// this clipboard. The changes are only seen with focus, unless the data
+    /// control protocol is available, see [`Clipboard::backend`].
+    pub fn subscribe(&self) -> Receiver<SelectionChange> {
+        let (subscriber, receiver) = mpsc::channel();
+        let _ = self.request_sender.send(worker::Command::Subscribe(subscriber));
+        receiver
+    }
+
+    #[cfg(feature = "async")]
+    fn load_with_reply(
+        &self,
+        ty: SelectionTarget,
+        request: LoadRequest,
+    ) -> future::ReplyFuture<Vec<u8>> {
+        let id = self.next_load_id.fetch_add(1, Ordering::Relaxed);
+        let canceller = LoadCanceller::new(id, self.request_sender.clone());
+        let (reply_tx, reply) = future::channel(canceller);
+        let reply_tx: worker::Reply<Vec<u8>> = Box::new(move |content| reply_tx.send(content));
+        let request = worker::Command::Load { id: Some(id), target: ty, request, reply: reply_tx };
+        let _ = self.request_sender.send(request);
+        reply
+    }
+
+    fn start_load_request<T>(
+        &self,
+        ty: SelectionTarget,
+        request: LoadRequest,
+        convert: fn(Vec<u8>) -> Result<T>,
+    ) -> LoadHandle<T> {
+        let id = self.next_load_id.fetch_add(1, Ordering::Relaxed);
+        let (reply_tx, reply_rx) = mpsc::channel();
+        let reply: worker::Reply<Vec<u8>> = Box::new(move |content| {
+            let _ = reply_tx.send(content);
+        });
+        let request = worker::Command::Load { id: Some(id), target: ty, request, reply };
         let _ = self.request_sender.send(request);
+        LoadHandle::new(id, self.request_sender.clone(), reply_rx, convert)
     }
+
+    fn owns(&self, ty: SelectionTarget) -> bool {
+        let owns = self.request_with_reply(|reply_tx| worker::Command::OwnsSelection(ty, reply_tx));
+        owns.unwrap_or(false)
+    }
+
+    /// Send the request carrying its own reply channel and wait for the reply.
+    fn request_with_reply<T, F>(&self, request: F) -> Result<T>
+    where
+        F: FnOnce(mpsc::Sender<Result<T>>) -> worker::Command,
+    {
+        let (reply_tx, reply_rx) = mpsc::channel();
+        let _ = self.request_sender.send(request(reply_tx));
+        reply_rx.recv().unwrap_or(Err(ClipboardError::WorkerDead))
+    }
+}
+
+// Requests carry their own reply channels, so the clipboard could be shared
+// between threads.
+const _: () = {
+    const fn assert_sync<T: Send + Sync>() {}
+    assert_sync::<Clipboard>();
+};
+
+/// Convert the loaded text content into a string.
+fn text_from_utf8(content: Vec<u8>) -> Result<String> {
+    String::from_utf8(content).map_err(|err| Error::new(ErrorKind::InvalidData, err).into())
 }
 
 impl Drop for Clipboard {
@@ -0,0 +1,146 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+use sctk::reexports::calloop::channel::Sender;
+
+use crate::error::{ClipboardError, Result};
+use crate::worker::Command;
+
+/// Identifier of the in-flight load.
+pub type LoadId = u64;
+
+/// Handle to the in-flight clipboard load.
+///
+/// Dropping the handle cancels the load if it hasn't finished yet.
+pub struct LoadHandle<T> {
+    reply_rx: Receiver<Result<Vec<u8>>>,
+    canceller: LoadCanceller,
+    convert: fn(Vec<u8>) -> Result<T>,
+    finished: bool,
+}
+
+impl<T> LoadHandle<T> {
+    pub(crate) fn new(
+        id: LoadId,
+        request_sender: Sender<Command>,
+        reply_rx: Receiver<Result<Vec<u8>>>,
+        convert: fn(Vec<u8>) -> Result<T>,
+    ) -> Self {
+        let canceller = LoadCanceller::new(id, request_sender);
+        Self { reply_rx, canceller, convert, finished: false }
+    }
+
+    /// Wait for the load to finish.
+    ///
+    /// Returns [`ClipboardError::Cancelled`] when the load was cancelled.
+    pub fn wait(mut self) -> Result<T> {
+        let reply = self.reply_rx.recv();
+        self.finished = true;
+        match reply {
+            Ok(reply) => reply.and_then(self.convert),
+            Err(_) => Err(self.canceller.disconnected_error()),
+        }
+    }
+
+    /// Wait for the load to finish for at most `timeout`.
+    ///
+    /// The load is cancelled once the `timeout` passes, returning
+    /// [`ClipboardError::Timeout`].
+    pub fn wait_timeout(mut self, timeout: Duration) -> Result<T> {
+        let reply = self.reply_rx.recv_timeout(timeout);
+        self.finished = true;
+        match reply {
+            Ok(reply) => reply.and_then(self.convert),
+            Err(RecvTimeoutError::Timeout) => {
+                self.canceller.cancel();
+                Err(ClipboardError::Timeout)
+            },
+            Err(RecvTimeoutError::Disconnected) => Err(self.canceller.disconnected_error()),
+        }
+    }
+
+    /// Cancel the load.
+    ///
+    /// Stops reading the clipboard content and closes the pipe it's read from.
+    /// Does nothing if the load has already finished.
+    pub fn cancel(&self) {
+        self.canceller.cancel();
+    }
+
+    /// Get the canceller of the load, which could be used from other threads.
+    pub fn canceller(&self) -> LoadCanceller {
+        self.canceller.clone()
+    }
+}
+
+impl<T> Drop for LoadHandle<T> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.canceller.cancel();
+        }
+    }
+}
+
+/// Canceller of the in-flight clipboard load.
+#[derive(Clone)]
+pub struct LoadCanceller {
+    id: LoadId,
+    cancelled: Arc<AtomicBool>,
+    request_sender: Sender<Command>,
+}
+
+impl LoadCanceller {
+    pub(crate) fn new(id: LoadId, request_sender: Sender<Command>) -> Self {
+        Self { id, cancelled: Default::default(), request_sender }
+    }
+
+    /// Cancel the load.
+    ///
+    /// Stops reading the clipboard content and closes the pipe it's read from.
+    /// Does nothing if the load has already finished.
+    pub fn cancel(&self) {
+        if !self.cancelled.swap(true, Ordering::AcqRel) {
+            let _ = self.request_sender.send(Command::CancelLoad(self.id));
+        }
+    }
+
+    /// Error for the reply channel disconnected without a reply.
+    fn disconnected_error(&self) -> ClipboardError {
+        if self.cancelled.load(Ordering::Acquire) {
+            ClipboardError::Cancelled
+        } else {
+            ClipboardError::WorkerDead
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::*;
+    use crate::state::SelectionTarget;
+    use crate::test_compositor::{DataControlProtocol, TestCompositor, connect};
+
+    #[test]
+    fn timed_out_load_is_cancelled() {
+        let (compositor, _connection, clipboard) = connect(DataControlProtocol::ext());
+        compositor.set_stalled_selection(SelectionTarget::Clipboard, &["text/plain"]);
+        TestCompositor::wait_for(|| {
+            clipboard.available_mime_types().is_ok_and(|mime_types| !mime_types.is_empty())
+        });
+
+        let err = clipboard.load_timeout(Duration::from_millis(100)).unwrap_err();
+        assert!(matches!(err, ClipboardError::Timeout));
+
+        // The pipe is closed once the load is cancelled.
+        TestCompositor::wait_for(|| {
+            let written = compositor.write_stalled(b"late");
+            written.is_some_and(|written| {
+                written.is_err_and(|err| err.kind() == io::ErrorKind::BrokenPipe)
+            })
+        });
+    }
+}
@@ -0,0 +1,297 @@
+use std::mem;
+use std::time::Duration;
+
+use sctk::reexports::calloop::timer::{TimeoutAction, Timer};
+use sctk::reexports::calloop::{LoopHandle, RegistrationToken};
+
+use crate::content::ClipboardContent;
+use crate::history::History;
+use crate::mime::PASSWORD_MANAGER_HINT_MIME_TYPE;
+use crate::state::{SelectionTarget, State};
+
+/// How long the copy of the selection could take before it's dropped.
+const COPY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Clipboard manager keeping the content of other clients alive and the
+/// history of the clipboard.
+///
+/// The selections of other clients are copied as soon as they're offered, so
+/// the last content could be offered by us once its owner is gone.
+#[derive(Debug)]
+pub struct ClipboardManager {
+    loop_handle: LoopHandle<'static, State>,
+    clipboard: ManagedSelection,
+    primary: ManagedSelection,
+    /// Whether the copies are offered once their owners are gone.
+    keep_alive: bool,
+    /// History of the clipboard selection.
+    history: Option<History>,
+}
+
+/// Copy of the selection owned by another client.
+#[derive(Debug, Default)]
+struct ManagedSelection {
+    /// Generation of the copy, telling apart the transfers of replaced
+    /// selections.
+    generation: u64,
+    /// The data copied so far for each mime type.
+    data: Vec<(String, Vec<u8>)>,
+    /// Number of mime types still being copied.
+    pending: usize,
+    /// Whether the owner is gone while the copy was in progress.
+    orphaned: bool,
+    /// Event sources of the copy in progress, including its timeout.
+    transfers: Vec<RegistrationToken>,
+}
+
+impl ClipboardManager {
+    pub fn new(loop_handle: LoopHandle<'static, State>) -> Self {
+        Self {
+            loop_handle,
+            clipboard: Default::default(),
+            primary: Default::default(),
+            keep_alive: false,
+            history: None,
+        }
+    }
+
+    /// Set whether the copies are offered once their owners are gone.
+    pub fn set_keep_alive(&mut self, keep_alive: bool) {
+        self.keep_alive = keep_alive;
+    }
+
+    /// Keep the history with the given capacity, or drop it without one.
+    pub fn set_history(&mut self, capacity: Option<usize>) {
+        match (capacity, &mut self.history) {
+            (Some(capacity), Some(history)) => history.set_capacity(capacity),
+            (Some(capacity), None) => self.history = Some(History::new(capacity)),
+            (None, _) => self.history = None,
+        }
+    }
+
+    pub fn history(&self) -> Option<&History> {
+        self.history.as_ref()
+    }
+
+    pub fn history_mut(&mut self) -> Option<&mut History> {
+        self.history.as_mut()
+    }
+
+    /// Whether the manager has nothing to do.
+    pub fn is_idle(&self) -> bool {
+        !self.keep_alive && self.history.is_none()
+    }
+
+    /// Start copying the new selection offering `mime_types`.
+    ///
+    /// Returns the generation of the copy, or `None` when the selection must
+    /// not be kept, like the secrets of password managers.
+    pub fn start_copy(&mut self, ty: SelectionTarget, mime_types: &[String]) -> Option<u64> {
+        self.forget(ty);
+
+        // Only the clipboard selection is kept in the history.
+        let wanted =
+            self.keep_alive || (ty == SelectionTarget::Clipboard && self.history.is_some());
+        let sensitive = mime_types.iter().any(|mime| mime == PASSWORD_MANAGER_HINT_MIME_TYPE);
+        if !wanted || sensitive || mime_types.is_empty() {
+            return None;
+        }
+
+        let generation = self.selection_mut(ty).generation;
+        let timer = Timer::from_duration(COPY_TIMEOUT);
+        let timer = self.loop_handle.insert_source(timer, move |_, _, state| {
+            state.selection_copy_timed_out(ty, generation);
+            TimeoutAction::Drop
+        });
+
+        let selection = self.selection_mut(ty);
+        selection.pending = mime_types.len();
+        selection.transfers.extend(timer.ok());
+        Some(generation)
+    }
+
+    /// Track the event source of the transfer, stopped once the copy is
+    /// forgotten.
+    pub fn add_transfer(&mut self, ty: SelectionTarget, token: RegistrationToken) {
+        self.selection_mut(ty).transfers.push(token);
+    }
+
+    /// Drop the copy which is still in progress after the timeout.
+    pub fn timed_out(&mut self, ty: SelectionTarget, generation: u64) {
+        let selection = self.selection_mut(ty);
+        if selection.generation == generation && selection.pending > 0 {
+            self.forget(ty);
+        }
+    }
+
+    /// Store the copied data of the `mime_type`.
+    ///
+    /// Returns the content to offer when the owner is already gone and this
+    /// was the last transfer.
+    pub fn copied(
+        &mut self,
+        ty: SelectionTarget,
+        generation: u64,
+        mime_type: String,
+        data: Option<Vec<u8>>,
+    ) -> Option<ClipboardContent> {
+        let keep_alive = self.keep_alive;
+        let selection = self.selection_mut(ty);
+        if selection.generation != generation || selection.pending == 0 {
+            return None;
+        }
+
+        selection.pending -= 1;
+        if let Some(data) = data {
+            selection.data.push((mime_type, data));
+        }
+
+        if selection.pending > 0 {
+            return None;
+        }
+
+        // Stop the timeout, the transfers are already gone.
+        for token in mem::take(&mut selection.transfers) {
+            self.loop_handle.remove(token);
+        }
+
+        let selection = self.selection_mut(ty);
+
+        // The data is only needed later to offer it ourselves.
+        let orphaned = selection.orphaned;
+        let data = match keep_alive {
+            true => selection.data.clone(),
+            false => mem::take(&mut selection.data),
+        };
+        self.record(ty, data);
+
+        if orphaned { self.take_copy(ty) } else { None }
+    }
+
+    /// Add the complete content of the selection to the history.
+    pub fn record(&mut self, ty: SelectionTarget, data: Vec<(String, Vec<u8>)>) {
+        if let (SelectionTarget::Clipboard, Some(history)) = (ty, &mut self.history) {
+            history.push(data);
+        }
+    }
+
+    /// Take the copy of the selection whose owner is gone.
+    ///
+    /// The copy in progress is offered once it's complete.
+    pub fn take_copy(&mut self, ty: SelectionTarget) -> Option<ClipboardContent> {
+        if !self.keep_alive {
+            self.forget(ty);
+            return None;
+        }
+
+        let selection = self.selection_mut(ty);
+        if selection.pending > 0 {
+            selection.orphaned = true;
+            return None;
+        }
+
+        let data = mem::take(&mut selection.data);
+        self.forget(ty);
+        if data.is_empty() {
+            return None;
+        }
+
+        let content = data
+            .into_iter()
+            .fold(ClipboardContent::new(), |content, (mime, data)| content.with_mime(mime, data));
+        Some(content)
+    }
+
+    /// Drop the copy of the selection, which won't be offered again.
+    ///
+    /// The transfers still in progress are stopped.
+    pub fn forget(&mut self, ty: SelectionTarget) {
+        let selection = self.selection_mut(ty);
+        let generation = selection.generation + 1;
+        let forgotten =
+            mem::replace(selection, ManagedSelection { generation, ..Default::default() });
+        for token in forgotten.transfers {
+            self.loop_handle.remove(token);
+        }
+    }
+
+    fn selection_mut(&mut self, ty: SelectionTarget) -> &mut ManagedSelection {
+        match ty {
+            SelectionTarget::Clipboard => &mut self.clipboard,
+            SelectionTarget::Primary => &mut self.primary,
+        }
+    }
+}
+
+impl Drop for ClipboardManager {
+    fn drop(&mut self) {
+        self.forget(SelectionTarget::Clipboard);
+        self.forget(SelectionTarget::Primary);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sctk::reexports::calloop::{EventLoop, ping};
+
+    use super::*;
+    use crate::test_compositor::{DataControlProtocol, TestCompositor, connect};
+
+    #[test]
+    fn offers_content_of_exited_owner() {
+        let (compositor, _connection, clipboard) = connect(DataControlProtocol::ext());
+        clipboard.set_manager_mode(true).unwrap();
+
+        for ty in [SelectionTarget::Clipboard, SelectionTarget::Primary] {
+            let content: [(&str, &[u8]); 2] =
+                [("text/plain", b"external"), ("text/html", b"<b>external</b>")];
+            compositor.set_selection(ty, &content);
+            compositor.clear_selection(ty);
+
+            TestCompositor::wait_for(|| compositor.client_selection(ty).is_some());
+            let mut mime_types = compositor.client_selection(ty).unwrap();
+            mime_types.sort();
+            assert_eq!(mime_types, ["text/html", "text/plain"]);
+            assert_eq!(compositor.read_selection(ty, "text/html"), b"<b>external</b>");
+        }
+    }
+
+    #[test]
+    fn timed_out_copy_is_stopped() {
+        let event_loop = EventLoop::try_new().unwrap();
+        let mut manager = ClipboardManager::new(event_loop.handle());
+        manager.set_keep_alive(true);
+        let ty = SelectionTarget::Clipboard;
+
+        let mime_types = [String::from("text/plain")];
+        let generation = manager.start_copy(ty, &mime_types).unwrap();
+        let (_ping, transfer) = ping::make_ping().unwrap();
+        let token = event_loop.handle().insert_source(transfer, |_, _, _| ()).unwrap();
+        manager.add_transfer(ty, token);
+        assert!(event_loop.handle().update(&token).is_ok());
+
+        manager.timed_out(ty, generation);
+        assert!(event_loop.handle().update(&token).is_err());
+        assert!(
+            manager.copied(ty, generation, mime_types[0].clone(), Some(b"late".into())).is_none()
+        );
+        assert!(manager.take_copy(ty).is_none());
+    }
+
+    #[test]
+    fn secrets_are_not_copied() {
+        let event_loop = EventLoop::try_new().unwrap();
+        let mut manager = ClipboardManager::new(event_loop.handle());
+        manager.set_keep_alive(true);
+        let ty = SelectionTarget::Clipboard;
+
+        let mime_types = [String::from("text/plain"), PASSWORD_MANAGER_HINT_MIME_TYPE.into()];
+        assert_eq!(manager.start_copy(ty, &mime_types), None);
+        assert!(manager.take_copy(ty).is_none());
+
+        let generation = manager.start_copy(ty, &mime_types[..1]).unwrap();
+        assert!(manager.take_copy(ty).is_none());
+        let content = manager.copied(ty, generation, mime_types[0].clone(), Some(b"text".into()));
+        assert_eq!(content.unwrap().mime_types().collect::<Vec<_>>(), ["text/plain"]);
+    }
+}
@@ -2,6 +2,14 @@
 pub static ALLOWED_MIME_TYPES: [&str; 3] =
     ["text/plain;charset=utf-8", "UTF8_STRING", "text/plain"];
 
+/// HTML mime type.
+pub static HTML_MIME_TYPE: &str = "text/html";
+
+/// Password manager hint mime type.
+///
+/// Set to `secret` to tell the clipboard managers not to record the content.
+pub static PASSWORD_MANAGER_HINT_MIME_TYPE: &str = "x-kde-passwordManagerHint";
+
 /// Mime type supported by clipboard.
 #[derive(Clone, Copy, Eq, PartialEq, Debug)]
 pub enum MimeType {
@@ -56,3 +64,66 @@ impl std::fmt::Display for MimeType {
 pub fn normalize_to_lf(text: String) -> String {
     text.replace("\r\n", "\n").replace('\r', "\n")
 }
+
+/// Decode HTML read from the clipboard into a string.
+///
+/// While HTML is usually UTF-8, some browsers put UTF-16 encoded HTML on the
+/// clipboard, with or without byte order mark.
+pub fn decode_html(data: Vec<u8>) -> String {
+    let utf16 = |data: &[u8], from_bytes: fn([u8; 2]) -> u16| {
+        let units: Vec<u16> =
+            data.chunks_exact(2).map(|unit| from_bytes([unit[0], unit[1]])).collect();
+        String::from_utf16_lossy(&units)
+    };
+
+    match data.as_slice() {
+        [0xef, 0xbb, 0xbf, html @ ..] => String::from_utf8_lossy(html).into_owned(),
+        [0xff, 0xfe, html @ ..] => utf16(html, u16::from_le_bytes),
+        [0xfe, 0xff, html @ ..] => utf16(html, u16::from_be_bytes),
+        // HTML starts with ASCII, so UTF-16 without BOM has a zero byte around it.
+        [_, 0, ..] if data.len() % 2 == 0 => utf16(&data, u16::from_le_bytes),
+        [0, _, ..] if data.len() % 2 == 0 => utf16(&data, u16::from_be_bytes),
+        _ => match String::from_utf8(data) {
+            Ok(html) => html,
+            Err(err) => String::from_utf8_lossy(err.as_bytes()).into_owned(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HTML: &str = "<b>héllo</b>";
+
+    fn utf16(html: &str, to_bytes: fn(u16) -> [u8; 2]) -> Vec<u8> {
+        html.encode_utf16().flat_map(to_bytes).collect()
+    }
+
+    #[test]
+    fn html_is_decoded_from_utf8() {
+        assert_eq!(decode_html(HTML.into()), HTML);
+
+        let mut data = vec![0xef, 0xbb, 0xbf];
+        data.extend_from_slice(HTML.as_bytes());
+        assert_eq!(decode_html(data), HTML);
+    }
+
+    #[test]
+    fn html_is_decoded_from_utf16_with_bom() {
+        let mut data = vec![0xff, 0xfe];
+        data.extend(utf16(HTML, u16::to_le_bytes));
+        assert_eq!(decode_html(data), HTML);
+
+        let mut data = vec![0xfe, 0xff];
+        data.extend(utf16(HTML, u16::to_be_bytes));
+        assert_eq!(decode_html(data), HTML);
+    }
+
+    #[test]
+    fn html_is_decoded_from_utf16_without_bom() {
+        // Firefox puts UTF-16 without BOM on the clipboard.
+        assert_eq!(decode_html(utf16(HTML, u16::to_le_bytes)), HTML);
+        assert_eq!(decode_html(utf16(HTML, u16::to_be_bytes)), HTML);
+    }
+}
@@ -1,17 +1,22 @@
-use std::borrow::Cow;
 use std::collections::HashMap;
-use std::io::{Error, ErrorKind, Read, Result, Write};
-use std::mem;
-use std::os::unix::io::{AsRawFd, RawFd};
-use std::rc::Rc;
-use std::sync::mpsc::Sender;
-
-use sctk::data_device_manager::data_device::{DataDevice, DataDeviceHandler};
-use sctk::data_device_manager::data_offer::{DataOfferError, DataOfferHandler, DragOffer};
+use std::fs::File;
+use std::io::{ErrorKind, Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::sync::mpsc::{self, Sender};
+use std::time::Duration;
+use std::{mem, thread};
+
+use sctk::data_device_manager::data_device::{DataDevice, DataDeviceData, DataDeviceHandler};
+use sctk::data_device_manager::data_offer::{
+    self, DataOfferError, DataOfferHandler, DragOffer, SelectionOffer,
+};
 use sctk::data_device_manager::data_source::{CopyPasteSource, DataSourceHandler};
-use sctk::data_device_manager::{DataDeviceManagerState, WritePipe};
+use sctk::data_device_manager::{DataDeviceManagerState, ReadPipe, WritePipe};
 use sctk::primary_selection::PrimarySelectionManagerState;
-use sctk::primary_selection::device::{PrimarySelectionDevice, PrimarySelectionDeviceHandler};
+use sctk::primary_selection::device::{
+    PrimarySelectionDevice, PrimarySelectionDeviceData, PrimarySelectionDeviceHandler,
+};
+use sctk::primary_selection::offer::PrimarySelectionOffer;
 use sctk::primary_selection::selection::{PrimarySelectionSource, PrimarySelectionSourceHandler};
 use sctk::registry::{ProvidesRegistryState, RegistryState};
 use sctk::seat::pointer::{PointerData, PointerEvent, PointerEventKind, PointerHandler};
@@ -21,11 +26,14 @@ use sctk::{
     delegate_seat, registry_handlers,
 };
 
-use sctk::reexports::calloop::{LoopHandle, PostAction};
+use sctk::reexports::calloop::timer::{TimeoutAction, Timer};
+use sctk::reexports::calloop::{LoopHandle, PostAction, RegistrationToken, channel};
 use sctk::reexports::client::globals::GlobalList;
+use sctk::reexports::client::protocol::wl_callback::{self, WlCallback};
 use sctk::reexports::client::protocol::wl_data_device::WlDataDevice;
 use sctk::reexports::client::protocol::wl_data_device_manager::DndAction;
 use sctk::reexports::client::protocol::wl_data_source::WlDataSource;
+use sctk::reexports::client::protocol::wl_display::WlDisplay;
 use sctk::reexports::client::protocol::wl_keyboard::WlKeyboard;
 use sctk::reexports::client::protocol::wl_pointer::WlPointer;
 use sctk::reexports::client::protocol::wl_seat::WlSeat;
@@ -37,14 +45,53 @@ use sctk::reexports::protocols::wp::primary_selection::zv1::client::{
 };
 use wayland_backend::client::ObjectId;
 
-use crate::mime::{ALLOWED_MIME_TYPES, MimeType, normalize_to_lf};
+use crate::content::{ClipboardContent, SelectionContent, SendData};
+use crate::data_control::{
+    DataControlDevice, DataControlOffer, DataControlSource, DataControlState,
+};
+use crate::error::{ClipboardError, Result};
+use crate::event::{SelectionChange, StoredSelection};
+use crate::history::{History, HistoryEntry, HistoryId};
+use crate::load::LoadId;
+use crate::manager::ClipboardManager;
+use crate::mime::{MimeType, PASSWORD_MANAGER_HINT_MIME_TYPE, normalize_to_lf};
+use crate::worker::Reply;
+
+/// Size of the chunks data is streamed to the pasting client in.
+const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+/// How long the paste could go on in other mime types after its last transfer
+/// before the content with no transfers left is withdrawn.
+const PASTE_GRACE_PERIOD: Duration = Duration::from_millis(500);
 
 pub struct State {
     pub primary_selection_manager_state: Option<PrimarySelectionManagerState>,
     pub data_device_manager_state: Option<DataDeviceManagerState>,
-    pub reply_tx: Sender<Result<String>>,
+    pub selection_subscribers: Vec<Sender<SelectionChange>>,
     pub exit: bool,
 
+    /// Loads which could be cancelled.
+    pending_loads: HashMap<LoadId, RegistrationToken>,
+
+    /// Subscribers to the cancellation of our sources.
+    cancel_subscribers: HashMap<ObjectId, Sender<()>>,
+    /// Stores waiting for the compositor to take their sources.
+    pending_stores: HashMap<ObjectId, (StoredSelection, Sender<Result<StoredSelection>>)>,
+
+    /// Remaining complete transfers of our sources with limited transfers.
+    remaining_transfers: HashMap<ObjectId, TransferLimit>,
+
+    /// Our newest sources the compositor didn't confirm or ignore yet.
+    unconfirmed_sources: HashMap<SelectionTarget, ObjectId>,
+    /// Our sources the compositor confirmed to serve the selections.
+    selection_owners: HashMap<SelectionTarget, ObjectId>,
+    /// Number of the selection changes seen for each target.
+    selection_changes: HashMap<SelectionTarget, u64>,
+    /// Number of the ownership checks waiting for the compositor.
+    pending_checks: HashMap<SelectionTarget, usize>,
+    /// The display to synchronize with the compositor through.
+    display: WlDisplay,
+
     registry_state: RegistryState,
     seat_state: SeatState,
 
@@ -55,11 +102,17 @@ pub struct State {
     loop_handle: LoopHandle<'static, Self>,
     queue_handle: QueueHandle<Self>,
 
-    primary_sources: Vec<PrimarySelectionSource>,
-    primary_selection_content: Rc<[u8]>,
+    /// Access to the selections without focus, preferred when available.
+    data_control_state: Option<DataControlState>,
+
+    /// Clipboard manager keeping the content of other clients alive.
+    manager: Option<ClipboardManager>,
 
-    data_sources: Vec<CopyPasteSource>,
-    data_selection_content: Rc<[u8]>,
+    primary_sources: Vec<SelectionSource>,
+    primary_selection_content: SelectionContent,
+
+    data_sources: Vec<SelectionSource>,
+    data_selection_content: SelectionContent,
 }
 
 impl State {
@@ -68,7 +121,7 @@ impl State {
         globals: &GlobalList,
         queue_handle: &QueueHandle<Self>,
         loop_handle: LoopHandle<'static, Self>,
-        reply_tx: Sender<Result<String>>,
+        display: WlDisplay,
     ) -> Option<Self> {
         // NOTE: while it's mutable, it's not part of the hash compute.
         #[allow(clippy::mutable_key_type)]
@@ -77,197 +130,899 @@ impl State {
         let data_device_manager_state = DataDeviceManagerState::bind(globals, queue_handle).ok();
         let primary_selection_manager_state =
             PrimarySelectionManagerState::bind(globals, queue_handle).ok();
+        let data_control_state = DataControlState::bind(globals, queue_handle).ok();
 
-        // When both globals are not available nothing could be done.
-        if data_device_manager_state.is_none() && primary_selection_manager_state.is_none() {
+        // When none of the globals are available nothing could be done.
+        if data_device_manager_state.is_none()
+            && primary_selection_manager_state.is_none()
+            && data_control_state.is_none()
+        {
             return None;
         }
 
         let seat_state = SeatState::new(globals, queue_handle);
         for seat in seat_state.seats() {
-            seats.insert(seat.id(), Default::default());
+            let data_control_device =
+                data_control_state.as_ref().map(|mgr| mgr.get_data_device(queue_handle, &seat));
+            seats.insert(seat.id(), ClipboardSeatState::new(data_control_device));
         }
 
         Some(Self {
             registry_state: RegistryState::new(globals),
-            primary_selection_content: Rc::from([]),
-            data_selection_content: Rc::from([]),
+            primary_selection_content: SelectionContent::default(),
+            data_selection_content: SelectionContent::default(),
             queue_handle: queue_handle.clone(),
             primary_selection_manager_state,
             primary_sources: Vec::new(),
             data_device_manager_state,
             data_sources: Vec::new(),
+            data_control_state,
+            manager: None,
+            selection_subscribers: Vec::new(),
+            pending_loads: HashMap::new(),
+            cancel_subscribers: HashMap::new(),
+            pending_stores: HashMap::new(),
+            remaining_transfers: HashMap::new(),
+            unconfirmed_sources: HashMap::new(),
+            selection_owners: HashMap::new(),
+            selection_changes: HashMap::new(),
+            pending_checks: HashMap::new(),
+            display,
             latest_seat: None,
             loop_handle,
             exit: false,
             seat_state,
-            reply_tx,
             seats,
         })
     }
 
     /// Store selection for the given target.
     ///
-    /// Selection source is only created when `Some(())` is returned.
-    pub fn store_selection(&mut self, ty: SelectionTarget, contents: String) -> Option<()> {
-        let latest = self.latest_seat.as_ref()?;
-        let seat = self.seats.get_mut(latest)?;
+    /// Returns the id of the created selection source.
+    pub fn store_selection(
+        &mut self,
+        ty: SelectionTarget,
+        contents: ClipboardContent,
+    ) -> Result<ObjectId> {
+        if !self.supports(ty) {
+            return Err(ClipboardError::ProtocolUnavailable);
+        }
+
+        if self.data_control(ty).is_some() {
+            return self.store_data_control_selection(ty, contents);
+        }
+
+        let latest = self.latest_seat.as_ref().ok_or(ClipboardError::NoSeat)?;
+        let seat = self.seats.get_mut(latest).ok_or(ClipboardError::NoSeat)?;
 
         if !seat.has_focus {
-            return None;
+            return Err(ClipboardError::NoFocus);
         }
 
-        let contents = Rc::from(contents.into_bytes());
+        let contents = SelectionContent::from(contents);
+        let max_transfers = contents.max_transfers();
 
-        match ty {
+        let source = match ty {
             SelectionTarget::Clipboard => {
-                let mgr = self.data_device_manager_state.as_ref()?;
-                self.data_selection_content = contents;
+                let mgr = self.data_device_manager_state.as_ref().unwrap();
                 let source =
-                    mgr.create_copy_paste_source(&self.queue_handle, ALLOWED_MIME_TYPES.iter());
+                    mgr.create_copy_paste_source(&self.queue_handle, contents.mime_types());
+                self.data_selection_content = contents;
                 source.set_selection(seat.data_device.as_ref().unwrap(), seat.latest_serial);
-                self.data_sources.push(source);
+                let id = source.inner().id();
+                self.data_sources.push(SelectionSource::CopyPaste(source));
+                id
             },
             SelectionTarget::Primary => {
-                let mgr = self.primary_selection_manager_state.as_ref()?;
+                let mgr = self.primary_selection_manager_state.as_ref().unwrap();
+                let source = mgr.create_selection_source(&self.queue_handle, contents.mime_types());
                 self.primary_selection_content = contents;
-                let source =
-                    mgr.create_selection_source(&self.queue_handle, ALLOWED_MIME_TYPES.iter());
                 source.set_selection(seat.primary_device.as_ref().unwrap(), seat.latest_serial);
+                let id = source.inner().id();
+                self.primary_sources.push(SelectionSource::Primary(source));
+                id
+            },
+        };
+
+        if let Some(max_transfers) = max_transfers {
+            self.remaining_transfers.insert(source.clone(), TransferLimit::new(max_transfers));
+        }
+
+        self.check_new_source(ty, source.clone());
+        Ok(source)
+    }
+
+    /// Store selection for the given target through the data control device,
+    /// which doesn't require focus.
+    fn store_data_control_selection(
+        &mut self,
+        ty: SelectionTarget,
+        contents: ClipboardContent,
+    ) -> Result<ObjectId> {
+        let mgr = self.data_control(ty).unwrap();
+        let seat = self.data_control_seat().ok_or(ClipboardError::NoSeat)?;
+        let device = seat.data_control_device.as_ref().unwrap();
+
+        let contents = SelectionContent::from(contents);
+        let source = mgr.create_source(&self.queue_handle, ty, contents.mime_types());
+        device.set_selection(ty, Some(&source));
+
+        let id = source.id();
+        if let Some(max_transfers) = contents.max_transfers() {
+            self.remaining_transfers.insert(id.clone(), TransferLimit::new(max_transfers));
+        }
+
+        let source = SelectionSource::DataControl(source);
+        match ty {
+            SelectionTarget::Clipboard => {
+                self.data_selection_content = contents;
+                self.data_sources.push(source);
+            },
+            SelectionTarget::Primary => {
+                self.primary_selection_content = contents;
                 self.primary_sources.push(source);
             },
         }
 
-        Some(())
+        self.check_new_source(ty, id.clone());
+        Ok(id)
     }
 
-    /// Load selection for the given target.
-    pub fn load_selection(&mut self, ty: SelectionTarget) -> Result<()> {
-        let latest = self
-            .latest_seat
-            .as_ref()
-            .ok_or_else(|| Error::other("no events received on any seat"))?;
-        let seat = self.seats.get_mut(latest).ok_or_else(|| Error::other("active seat lost"))?;
+    /// Check whether the new `source` serves the selection once the compositor
+    /// handled it.
+    ///
+    /// The compositor confirms the source by announcing a new selection, and
+    /// ignores it silently otherwise, like for an outdated serial.
+    fn check_new_source(&mut self, ty: SelectionTarget, source: ObjectId) {
+        let changes = self.selection_changes.get(&ty).copied().unwrap_or_default();
+        self.unconfirmed_sources.insert(ty, source.clone());
+        self.check_ownership(ty, source, Some(changes));
+    }
 
-        if !seat.has_focus {
-            return Err(Error::other("client doesn't have focus"));
+    /// Check whether the `source` serves the selection once the compositor
+    /// handled all of our requests, and cancelled the source if it was
+    /// replaced.
+    fn check_ownership(&mut self, ty: SelectionTarget, source: ObjectId, changes: Option<u64>) {
+        *self.pending_checks.entry(ty).or_default() += 1;
+        let check = OwnershipCheck { target: ty, source, changes };
+        self.display.sync(&self.queue_handle, check);
+    }
+
+    /// Whether the ownership of the selection for the given target isn't known
+    /// yet.
+    fn is_checking_ownership(&self, ty: SelectionTarget) -> bool {
+        self.pending_checks.get(&ty).is_some_and(|pending| *pending > 0)
+    }
+
+    /// Update the ownership once the compositor handled our requests.
+    fn ownership_checked(&mut self, check: &OwnershipCheck) {
+        let ty = check.target;
+        if let Some(pending) = self.pending_checks.get_mut(&ty) {
+            *pending = pending.saturating_sub(1);
+        }
+
+        if check.changes.is_some() && self.unconfirmed_sources.get(&ty) == Some(&check.source) {
+            self.unconfirmed_sources.remove(&ty);
         }
 
-        let (read_pipe, mime_type) = match ty {
+        let active = self.active_source(ty).as_ref() == Some(&check.source);
+        let changes = self.selection_changes.get(&ty).copied().unwrap_or_default();
+        let changed = check.changes.is_none_or(|before| changes > before);
+        let rejected = active && !changed;
+        if active && changed {
+            self.selection_owners.insert(ty, check.source.clone());
+        } else if rejected {
+            // The compositor ignored the source.
+            self.source_cancelled(ty, check.source.clone());
+        }
+
+        // A source replaced by our newer one was taken before that.
+        if let Some((stored, reply_tx)) = self.pending_stores.remove(&check.source) {
+            let _ =
+                reply_tx.send(if rejected { Err(ClipboardError::Rejected) } else { Ok(stored) });
+        }
+
+        // Handle the selection change which waited for the ownership.
+        self.manage_selection(ty);
+    }
+
+    /// Store selection for the given target, watching for its cancellation.
+    ///
+    /// Replies once the compositor took or ignored the new source.
+    pub fn try_store_selection(
+        &mut self,
+        ty: SelectionTarget,
+        contents: ClipboardContent,
+        reply_tx: Sender<Result<StoredSelection>>,
+    ) {
+        let source = match self.store_selection(ty, contents) {
+            Ok(source) => source,
+            Err(err) => {
+                let _ = reply_tx.send(Err(err));
+                return;
+            },
+        };
+
+        let (cancel_tx, cancel_rx) = mpsc::channel();
+        self.cancel_subscribers.insert(source.clone(), cancel_tx);
+        let stored = StoredSelection::new(ty, cancel_rx);
+        self.pending_stores.insert(source, (stored, reply_tx));
+    }
+
+    /// Store selection for the given target, withdrawing it after `expiry`.
+    ///
+    /// The selection is only withdrawn when it still holds this content.
+    pub fn store_selection_with_expiry(
+        &mut self,
+        ty: SelectionTarget,
+        contents: ClipboardContent,
+        expiry: Duration,
+    ) -> Result<()> {
+        let source = self.store_selection(ty, contents)?;
+
+        let timer = Timer::from_duration(expiry);
+        let timer = self.loop_handle.insert_source(timer, move |_, _, state| {
+            if state.active_source(ty).as_ref() == Some(&source) {
+                state.clear_selection(ty);
+            }
+            TimeoutAction::Drop
+        });
+
+        // Don't leave the content around when it can't expire.
+        if timer.is_err() {
+            self.clear_selection(ty);
+        }
+
+        Ok(())
+    }
+
+    /// The source serving the selection for the given target.
+    fn active_source(&self, ty: SelectionTarget) -> Option<ObjectId> {
+        match ty {
+            SelectionTarget::Clipboard => self.data_sources.last().map(SelectionSource::id),
+            SelectionTarget::Primary => self.primary_sources.last().map(SelectionSource::id),
+        }
+    }
+
+    /// Withdraw our selection for the given target.
+    ///
+    /// Does nothing when none of our sources are left, and only drops them
+    /// when the selection was taken by another client.
+    pub fn clear_selection(&mut self, ty: SelectionTarget) {
+        let Some(active) = self.active_source(ty) else {
+            return;
+        };
+
+        // The selection of another client could be announced before our source is
+        // cancelled, so it's only unset while it's ours or could still be.
+        if self.owns_selection(ty) || self.unconfirmed_sources.get(&ty) == Some(&active) {
+            self.unset_selection(ty);
+        }
+
+        let sources: Vec<ObjectId> = match ty {
             SelectionTarget::Clipboard => {
-                let selection = seat
-                    .data_device
-                    .as_ref()
-                    .and_then(|data| data.data().selection_offer())
-                    .ok_or_else(|| Error::other("selection is empty"))?;
-
-                let mime_type =
-                    selection.with_mime_types(MimeType::find_allowed).ok_or_else(|| {
-                        Error::new(ErrorKind::NotFound, "supported mime-type is not found")
-                    })?;
-
-                (
-                    selection.receive(mime_type.to_string()).map_err(|err| match err {
-                        DataOfferError::InvalidReceive => Error::other("offer is not ready yet"),
-                        DataOfferError::Io(err) => err,
-                    })?,
-                    mime_type,
-                )
+                self.data_selection_content.clear();
+                self.data_sources.drain(..).map(|source| source.id()).collect()
             },
             SelectionTarget::Primary => {
-                let selection = seat
-                    .primary_device
-                    .as_ref()
-                    .and_then(|data| data.data().selection_offer())
-                    .ok_or_else(|| Error::other("selection is empty"))?;
-
-                let mime_type =
-                    selection.with_mime_types(MimeType::find_allowed).ok_or_else(|| {
-                        Error::new(ErrorKind::NotFound, "supported mime-type is not found")
-                    })?;
-
-                (selection.receive(mime_type.to_string())?, mime_type)
+                self.primary_selection_content.clear();
+                self.primary_sources.drain(..).map(|source| source.id()).collect()
             },
         };
 
-        // Mark FD as non-blocking so we won't block ourselves.
-        set_non_blocking(read_pipe.as_raw_fd())?;
+        for source in sources {
+            self.source_cancelled(ty, source);
+        }
+    }
+
+    /// Unset the selection for the given target explicitly when we can,
+    /// destroying the source alone is enough for the compositor to drop it
+    /// otherwise.
+    fn unset_selection(&self, ty: SelectionTarget) {
+        let seat = self.latest_seat.as_ref().and_then(|seat| self.seats.get(seat));
+        if let Some(device) = self
+            .data_control_seat()
+            .and_then(|seat| seat.data_control_device.as_ref())
+            .filter(|device| device.supports(ty))
+        {
+            device.set_selection(ty, None);
+        } else if let Some(seat) = seat.filter(|seat| seat.has_focus) {
+            match ty {
+                SelectionTarget::Clipboard => {
+                    if let Some(device) = &seat.data_device {
+                        device.unset_selection(seat.latest_serial);
+                    }
+                },
+                SelectionTarget::Primary => {
+                    if let Some(device) = &seat.primary_device {
+                        device.unset_selection(seat.latest_serial);
+                    }
+                },
+            }
+        }
+    }
+
+    /// Start the transfer of the `mime_type` from the source.
+    ///
+    /// Returns `false` when the source has no transfers left for a new paste.
+    fn transfer_started(
+        &mut self,
+        ty: SelectionTarget,
+        source: &ObjectId,
+        mime_type: &str,
+    ) -> bool {
+        let Some(limit) = self.remaining_transfers.get_mut(source) else {
+            return true;
+        };
+
+        // The paste reads every mime type at most once, so the repeated one
+        // belongs to the next paste.
+        if limit.pasted.iter().any(|pasted| pasted == mime_type) {
+            if limit.remaining == 0 {
+                self.withdraw_source(ty, source.clone());
+                return false;
+            }
+
+            limit.pasted.clear();
+            limit.counted = false;
+        }
+        limit.pasted.push(mime_type.to_owned());
 
+        // Wait for the paste to finish before withdrawing the content.
+        if let Some(withdrawal) = limit.withdrawal.take() {
+            self.loop_handle.remove(withdrawal);
+        }
+
+        true
+    }
+
+    /// Count the paste once its first transfer from the source completes,
+    /// withdrawing the source once all of its pastes were served.
+    fn transfer_finished(&mut self, ty: SelectionTarget, source: &ObjectId) {
+        let Some(limit) = self.remaining_transfers.get_mut(source) else {
+            return;
+        };
+
+        if !limit.counted {
+            limit.counted = true;
+            limit.remaining = limit.remaining.saturating_sub(1);
+        }
+
+        if limit.remaining > 0 {
+            return;
+        }
+
+        // Let the paste go on in the other mime types for a while.
+        if let Some(withdrawal) = limit.withdrawal.take() {
+            self.loop_handle.remove(withdrawal);
+        }
+
+        let withdrawn = source.clone();
+        let timer = Timer::from_duration(PASTE_GRACE_PERIOD);
+        let withdrawal = self.loop_handle.insert_source(timer, move |_, _, state| {
+            state.withdraw_source(ty, withdrawn.clone());
+            TimeoutAction::Drop
+        });
+
+        match (withdrawal, self.remaining_transfers.get_mut(source)) {
+            (Ok(withdrawal), Some(limit)) => limit.withdrawal = Some(withdrawal),
+            _ => self.withdraw_source(ty, source.clone()),
+        }
+    }
+
+    /// Withdraw the source which served all of its transfers.
+    fn withdraw_source(&mut self, ty: SelectionTarget, source: ObjectId) {
+        if self.active_source(ty).as_ref() == Some(&source) {
+            self.clear_selection(ty);
+        } else {
+            // The source was already replaced, so only drop it.
+            self.source_cancelled(ty, source);
+        }
+    }
+
+    /// Drop the cancelled source, notifying its subscriber.
+    pub fn source_cancelled(&mut self, ty: SelectionTarget, source: ObjectId) {
+        match ty {
+            SelectionTarget::Clipboard => self.data_sources.retain(|data| data.id() != source),
+            SelectionTarget::Primary => {
+                self.primary_sources.retain(|primary| primary.id() != source)
+            },
+        }
+
+        if self.selection_owners.get(&ty) == Some(&source) {
+            self.selection_owners.remove(&ty);
+        }
+
+        if self.unconfirmed_sources.get(&ty) == Some(&source) {
+            self.unconfirmed_sources.remove(&ty);
+        }
+
+        if let Some(withdrawal) =
+            self.remaining_transfers.remove(&source).and_then(|limit| limit.withdrawal)
+        {
+            self.loop_handle.remove(withdrawal);
+        }
+
+        if let Some(subscriber) = self.cancel_subscribers.remove(&source) {
+            let _ = subscriber.send(());
+        }
+    }
+
+    /// Whether the selection for the given target is supported by the
+    /// compositor.
+    pub fn supports(&self, ty: SelectionTarget) -> bool {
+        if self.data_control(ty).is_some() {
+            return true;
+        }
+
+        match ty {
+            SelectionTarget::Clipboard => self.data_device_manager_state.is_some(),
+            SelectionTarget::Primary => self.primary_selection_manager_state.is_some(),
+        }
+    }
+
+    /// Load selection for the given target.
+    ///
+    /// The `reply` is called with the loaded content once the load finishes.
+    pub fn load_selection(
+        &mut self,
+        ty: SelectionTarget,
+        request: LoadRequest,
+        id: Option<LoadId>,
+        reply: Reply<Vec<u8>>,
+    ) {
+        // Our own content is subject to the same seat and focus checks as the
+        // content of other clients.
+        if let Err(err) = self.selection_offer(ty) {
+            return reply(Err(err));
+        }
+
+        // The compositor would just pipe our own content back to us, so serve it
+        // from memory instead, unless it's streamed.
+        if self.owns_selection(ty) {
+            let content = match ty {
+                SelectionTarget::Clipboard => &mut self.data_selection_content,
+                SelectionTarget::Primary => &mut self.primary_selection_content,
+            };
+            if let Some(content) = load_own_content(content, &request).transpose() {
+                return reply(content);
+            }
+        }
+
+        let (read_pipe, mime_type) = match self.receive_selection(ty, &request) {
+            Ok(receive) => receive,
+            Err(err) => return reply(Err(err)),
+        };
+
+        let token = self.read_to_end(read_pipe, move |state, content| {
+            if let Some(id) = id {
+                state.pending_loads.remove(&id);
+            }
+
+            let content = content.map(|content| match request {
+                LoadRequest::Text => text_from_bytes(content, &mime_type),
+                LoadRequest::Mime(_) => content,
+            });
+            reply(content.map_err(Into::into));
+        });
+
+        if let (Some(token), Some(id)) = (token, id) {
+            self.pending_loads.insert(id, token);
+        }
+    }
+
+    /// Read the non-blocking pipe to the end on the event loop, passing the
+    /// content to the `callback`.
+    fn read_to_end<F>(&self, read_pipe: ReadPipe, callback: F) -> Option<RegistrationToken>
+    where
+        F: FnOnce(&mut State, std::io::Result<Vec<u8>>) + 'static,
+    {
+        let mut callback = Some(callback);
         let mut reader_buffer = [0; 4096];
         let mut content = Vec::new();
-        let _ = self.loop_handle.insert_source(read_pipe, move |_, file, state| {
+        let token = self.loop_handle.insert_source(read_pipe, move |_, file, state| {
             let file = unsafe { file.get_mut() };
-            loop {
+            let result = loop {
                 match file.read(&mut reader_buffer) {
-                    Ok(0) => {
-                        let utf8 = String::from_utf8_lossy(&content);
-                        let content = match utf8 {
-                            Cow::Borrowed(_) => {
-                                // Don't clone the read data.
-                                let mut to_send = Vec::new();
-                                mem::swap(&mut content, &mut to_send);
-                                String::from_utf8(to_send).unwrap()
-                            },
-                            Cow::Owned(content) => content,
-                        };
-
-                        // Post-process the content according to mime type.
-                        let content = match mime_type {
-                            MimeType::TextPlainUtf8 | MimeType::TextPlain => {
-                                normalize_to_lf(content)
-                            },
-                            MimeType::Utf8String => content,
-                        };
-
-                        let _ = state.reply_tx.send(Ok(content));
-                        break PostAction::Remove;
-                    },
+                    Ok(0) => break Ok(mem::take(&mut content)),
                     Ok(n) => content.extend_from_slice(&reader_buffer[..n]),
-                    Err(err) if err.kind() == ErrorKind::WouldBlock => break PostAction::Continue,
-                    Err(err) => {
-                        let _ = state.reply_tx.send(Err(err));
-                        break PostAction::Remove;
+                    Err(err) if err.kind() == ErrorKind::WouldBlock => {
+                        return PostAction::Continue;
                     },
+                    Err(err) => break Err(err),
                 };
+            };
+
+            if let Some(callback) = callback.take() {
+                callback(state, result);
             }
+
+            PostAction::Remove
         });
 
+        token.ok()
+    }
+
+    /// Cancel the in-flight load, closing the pipe it reads from.
+    pub fn cancel_load(&mut self, id: LoadId) {
+        if let Some(token) = self.pending_loads.remove(&id) {
+            self.loop_handle.remove(token);
+        }
+    }
+
+    /// Start receiving the selection for the given target.
+    ///
+    /// Returns the non-blocking pipe to read the content from along with its
+    /// mime type.
+    fn receive_selection(
+        &self,
+        ty: SelectionTarget,
+        request: &LoadRequest,
+    ) -> Result<(ReadPipe, String)> {
+        let offer = self.selection_offer(ty)?;
+
+        let mime_type = match request {
+            LoadRequest::Text => offer
+                .with_mime_types(MimeType::find_allowed)
+                .ok_or(ClipboardError::UnsupportedMime)?
+                .to_string(),
+            LoadRequest::Mime(mime_type) => {
+                offer.ensure_offered(mime_type)?;
+                mime_type.clone()
+            },
+        };
+
+        let read_pipe = offer.receive(mime_type.clone())?;
+
+        // Mark FD as non-blocking so we won't block ourselves.
+        set_non_blocking(read_pipe.as_raw_fd())?;
+
+        Ok((read_pipe, mime_type))
+    }
+
+    /// Load selection for the given target as a stream.
+    ///
+    /// Returns the read end of the pipe the selection content is written to.
+    pub fn load_selection_stream(
+        &mut self,
+        ty: SelectionTarget,
+        mime_type: String,
+    ) -> Result<File> {
+        let offer = self.selection_offer(ty)?;
+        offer.ensure_offered(&mime_type)?;
+
+        let (read_fd, write_fd) = pipe()?;
+        offer.receive_to_fd(mime_type, write_fd);
+
+        Ok(File::from(read_fd))
+    }
+
+    /// Whether the selection for the given target is served by our source.
+    ///
+    /// The source must be confirmed by the latest selection change, so the
+    /// selection is not ours while another client's change is pending, even
+    /// before the compositor cancels our source.
+    pub fn owns_selection(&self, ty: SelectionTarget) -> bool {
+        let owner = self.selection_owners.get(&ty);
+        owner.is_some() && owner == self.active_source(ty).as_ref()
+    }
+
+    /// Mime types offered by the selection for the given target.
+    pub fn selection_mime_types(&self, ty: SelectionTarget) -> Result<Vec<String>> {
+        let offer = self.selection_offer(ty)?;
+        Ok(offer.with_mime_types(|mime_types| mime_types.to_vec()))
+    }
+
+    /// Notify subscribers about the selection change on the given seat.
+    pub fn notify_selection_change(&mut self, ty: SelectionTarget, seat: &WlSeat) {
+        // The change could be ours or of another client, which is only known
+        // once the compositor cancels our source or not.
+        *self.selection_changes.entry(ty).or_default() += 1;
+        self.selection_owners.remove(&ty);
+        if let Some(source) = self.active_source(ty) {
+            self.check_ownership(ty, source, None);
+        }
+
+        let seat_name = self.seat_state.info(seat).and_then(|info| info.name);
+        let mime_types = self
+            .seats
+            .get(&seat.id())
+            .and_then(|seat| seat.selection_offer(ty))
+            .map(|offer| offer.with_mime_types(|mime_types| mime_types.to_vec()))
+            .unwrap_or_default();
+
+        let change = SelectionChange { target: ty, seat_name, mime_types };
+
+        // Drop the subscribers which are gone.
+        self.selection_subscribers.retain(|subscriber| subscriber.send(change.clone()).is_ok());
+
+        self.manage_selection(ty);
+    }
+
+    /// Enable or disable the clipboard manager.
+    ///
+    /// The manager needs the data control protocol to watch the selections and
+    /// offer their content without focus.
+    pub fn set_manager_mode(&mut self, enabled: bool) -> Result<()> {
+        if enabled && self.data_control_state.is_none() {
+            return Err(ClipboardError::ProtocolUnavailable);
+        }
+
+        self.update_manager(enabled, |manager| manager.set_keep_alive(enabled));
+        Ok(())
+    }
+
+    /// Keep the clipboard history with the given capacity, or drop it without
+    /// one.
+    pub fn set_history_capacity(&mut self, capacity: Option<usize>) -> Result<()> {
+        if capacity.is_some() && !self.supports(SelectionTarget::Clipboard) {
+            return Err(ClipboardError::ProtocolUnavailable);
+        }
+
+        self.update_manager(capacity.is_some(), |manager| manager.set_history(capacity));
+        Ok(())
+    }
+
+    /// Entries of the clipboard history, the most recent first.
+    pub fn history_entries(&self) -> Vec<HistoryEntry> {
+        let history = self.manager.as_ref().and_then(ClipboardManager::history);
+        history.map(History::entries).unwrap_or_default()
+    }
+
+    /// Access the clipboard history.
+    ///
+    /// No entries could be found without the history.
+    pub fn with_history<T, F>(&mut self, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut History) -> Result<T>,
+    {
+        let history = self.manager.as_mut().and_then(ClipboardManager::history_mut);
+        history.map_or(Err(ClipboardError::UnknownHistoryEntry), f)
+    }
+
+    /// Offer the content of the history entry as the clipboard selection.
+    pub fn restore_history(&mut self, id: HistoryId) -> Result<()> {
+        let content = self.with_history(|history| history.content(id))?;
+        self.store_selection(SelectionTarget::Clipboard, content)?;
         Ok(())
     }
 
-    fn send_request(&mut self, ty: SelectionTarget, write_pipe: WritePipe, mime: String) {
-        // We can only send strings, so don't do anything with the mime-type.
-        if MimeType::find_allowed(&[mime]).is_none() {
+    /// Update the clipboard manager, dropping it once it has nothing to do.
+    ///
+    /// The current selections are copied right away when the manager got
+    /// `enabled` more.
+    fn update_manager<F: FnOnce(&mut ClipboardManager)>(&mut self, enabled: bool, update: F) {
+        let manager =
+            self.manager.get_or_insert_with(|| ClipboardManager::new(self.loop_handle.clone()));
+        update(manager);
+
+        if manager.is_idle() {
+            self.manager = None;
+        } else if enabled {
+            self.manage_selection(SelectionTarget::Clipboard);
+            self.manage_selection(SelectionTarget::Primary);
+        }
+    }
+
+    /// Copy the new selection for the given target, or offer the last copy
+    /// when the selection is gone along with its owner.
+    fn manage_selection(&mut self, ty: SelectionTarget) {
+        // Wait until it's known whether the selection is ours.
+        if self.manager.is_none() || self.is_checking_ownership(ty) {
             return;
         }
 
-        // Mark FD as non-blocking so we won't block ourselves.
-        if set_non_blocking(write_pipe.as_raw_fd()).is_err() {
+        let owned = self.owns_selection(ty);
+        let offer = self.selection_offer(ty);
+        let manager = self.manager.as_mut().unwrap();
+
+        // Our own content is already in memory.
+        if owned {
+            manager.forget(ty);
+            let content = match ty {
+                SelectionTarget::Clipboard => &mut self.data_selection_content,
+                SelectionTarget::Primary => &mut self.primary_selection_content,
+            };
+            manager.record(ty, content.copy_data());
+            return;
+        }
+
+        let offer = match offer {
+            Ok(offer) => offer,
+            Err(ClipboardError::EmptySelection) => {
+                if let Some(content) = manager.take_copy(ty) {
+                    let _ = self.store_selection(ty, content);
+                }
+                return;
+            },
+            Err(_) => return,
+        };
+
+        let mime_types = offer.with_mime_types(|mime_types| mime_types.to_vec());
+        let Some(generation) = manager.start_copy(ty, &mime_types) else {
+            return;
+        };
+
+        for mime_type in mime_types {
+            let read_pipe = offer.receive(mime_type.clone()).and_then(|read_pipe| {
+                set_non_blocking(read_pipe.as_raw_fd())?;
+                Ok(read_pipe)
+            });
+
+            match read_pipe {
+                Ok(read_pipe) => {
+                    let token = self.read_to_end(read_pipe, move |state, data| {
+                        state.selection_copied(ty, generation, mime_type, data.ok());
+                    });
+                    if let (Some(token), Some(manager)) = (token, self.manager.as_mut()) {
+                        manager.add_transfer(ty, token);
+                    }
+                },
+                Err(_) => self.selection_copied(ty, generation, mime_type, None),
+            }
+        }
+    }
+
+    /// Pass the copied data of the `mime_type` to the clipboard manager.
+    fn selection_copied(
+        &mut self,
+        ty: SelectionTarget,
+        generation: u64,
+        mime_type: String,
+        data: Option<Vec<u8>>,
+    ) {
+        let Some(manager) = self.manager.as_mut() else {
             return;
+        };
+
+        // The owner is already gone, so offer the complete copy right away.
+        if let Some(content) = manager.copied(ty, generation, mime_type, data) {
+            let _ = self.store_selection(ty, content);
+        }
+    }
+
+    /// Drop the copy of the selection which takes too long.
+    pub fn selection_copy_timed_out(&mut self, ty: SelectionTarget, generation: u64) {
+        if let Some(manager) = self.manager.as_mut() {
+            manager.timed_out(ty, generation);
+        }
+    }
+
+    /// Get the current selection offer for the given target.
+    fn selection_offer(&self, ty: SelectionTarget) -> Result<Offer> {
+        if self.data_control(ty).is_some() {
+            let seat = self.data_control_seat().ok_or(ClipboardError::NoSeat)?;
+            return seat.selection_offer(ty).ok_or(ClipboardError::EmptySelection);
+        }
+
+        let latest = self.latest_seat.as_ref().ok_or(ClipboardError::NoSeat)?;
+        let seat = self.seats.get(latest).ok_or(ClipboardError::NoSeat)?;
+
+        if !seat.has_focus {
+            return Err(ClipboardError::NoFocus);
+        }
+
+        seat.selection_offer(ty).ok_or(ClipboardError::EmptySelection)
+    }
+
+    /// The data control protocol, when it could access the given target.
+    fn data_control(&self, ty: SelectionTarget) -> Option<&DataControlState> {
+        self.data_control_state.as_ref().filter(|data_control| data_control.supports(ty))
+    }
+
+    /// The protocol used to access the clipboard selection.
+    pub fn backend(&self) -> ClipboardBackend {
+        match &self.data_control_state {
+            Some(data_control) => data_control.backend(),
+            None => ClipboardBackend::DataDevice,
+        }
+    }
+
+    /// The seat to access the selections through the data control device on.
+    ///
+    /// The latest seat which got an event is preferred.
+    fn data_control_seat(&self) -> Option<&ClipboardSeatState> {
+        let has_device = |seat: &&ClipboardSeatState| seat.data_control_device.is_some();
+        let latest = self.latest_seat.as_ref().and_then(|seat| self.seats.get(seat));
+        latest.filter(has_device).or_else(|| self.seats.values().find(has_device))
+    }
+
+    /// Forget the data control device of the `seat` which became invalid.
+    pub fn data_control_finished(&mut self, seat: &WlSeat) {
+        if let Some(seat) = self.seats.get_mut(&seat.id()) {
+            seat.data_control_device = None;
+        }
+    }
+
+    pub fn send_request(
+        &mut self,
+        ty: SelectionTarget,
+        source: ObjectId,
+        write_pipe: WritePipe,
+        mime: String,
+    ) {
+        // Clipboard managers read the hint before deciding whether to read the
+        // content, so it's not a paste.
+        let source = (mime != PASSWORD_MANAGER_HINT_MIME_TYPE).then_some(source);
+        if let Some(source) = &source {
+            if !self.transfer_started(ty, source, &mime) {
+                return;
+            }
         }
 
         // Don't access the content on the state directly, since it could change during
         // the send.
         let contents = match ty {
-            SelectionTarget::Clipboard => self.data_selection_content.clone(),
-            SelectionTarget::Primary => self.primary_selection_content.clone(),
+            SelectionTarget::Clipboard => self.data_selection_content.get(&mime),
+            SelectionTarget::Primary => self.primary_selection_content.get(&mime),
         };
 
-        let mut written = 0;
-        let _ = self.loop_handle.insert_source(write_pipe, move |_, file, _| {
-            let file = unsafe { file.get_mut() };
-            loop {
-                match file.write(&contents[written..]) {
-                    Ok(n) if written + n == contents.len() => {
-                        written += n;
-                        break PostAction::Remove;
-                    },
-                    Ok(n) => written += n,
-                    Err(err) if err.kind() == ErrorKind::WouldBlock => break PostAction::Continue,
-                    Err(_) => break PostAction::Remove,
+        // We don't have anything to send for the requested mime-type.
+        let Some(contents) = contents else {
+            return;
+        };
+
+        match contents {
+            SendData::Data(contents) => {
+                // Mark FD as non-blocking so we won't block ourselves.
+                if set_non_blocking(write_pipe.as_raw_fd()).is_err() {
+                    return;
                 }
-            }
-        });
+
+                let mut written = 0;
+                let _ = self.loop_handle.insert_source(write_pipe, move |_, file, state| {
+                    let file = unsafe { file.get_mut() };
+                    loop {
+                        match file.write(&contents[written..]) {
+                            Ok(n) if written + n == contents.len() => {
+                                written += n;
+                                if let Some(source) = &source {
+                                    state.transfer_finished(ty, source);
+                                }
+                                break PostAction::Remove;
+                            },
+                            Ok(n) => written += n,
+                            Err(err) if err.kind() == ErrorKind::WouldBlock => {
+                                break PostAction::Continue;
+                            },
+                            Err(_) => break PostAction::Remove,
+                        }
+                    }
+                });
+            },
+            SendData::Reader(mut reader) => {
+                // The reader could block, like the sockets do, so it's streamed
+                // to the blocking pipe on its own thread.
+                let (finished_tx, finished_rx) = channel::channel();
+                let mut write_pipe = write_pipe;
+                let stream = move || {
+                    // Only the chunk being written is held in memory.
+                    let mut buffer = vec![0; STREAM_CHUNK_SIZE];
+                    loop {
+                        let n = match reader.read(&mut buffer) {
+                            Ok(0) => break,
+                            Ok(n) => n,
+                            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                            Err(_) => return,
+                        };
+                        if write_pipe.write_all(&buffer[..n]).is_err() {
+                            return;
+                        }
+                    }
+                    let _ = finished_tx.send(());
+                };
+
+                if thread::Builder::new().name("clipboard-stream".into()).spawn(stream).is_err() {
+                    return;
+                }
+
+                let _ = self.loop_handle.insert_source(finished_rx, move |event, _, state| {
+                    if let (channel::Event::Msg(()), Some(source)) = (event, &source) {
+                        state.transfer_finished(ty, source);
+                    }
+                });
+            },
+        }
     }
 }
 
@@ -276,8 +1031,10 @@ impl SeatHandler for State {
         &mut self.seat_state
     }
 
-    fn new_seat(&mut self, _: &Connection, _: &QueueHandle<Self>, seat: WlSeat) {
-        self.seats.insert(seat.id(), Default::default());
+    fn new_seat(&mut self, _: &Connection, qh: &QueueHandle<Self>, seat: WlSeat) {
+        let data_control_device =
+            self.data_control_state.as_ref().map(|mgr| mgr.get_data_device(qh, &seat));
+        self.seats.insert(seat.id(), ClipboardSeatState::new(data_control_device));
     }
 
     fn new_capability(
@@ -407,7 +1164,17 @@ impl DataDeviceHandler for State {
     fn drop_performed(&mut self, _: &Connection, _: &QueueHandle<Self>, _: &WlDataDevice) {}
 
     // The selection is finished and ready to be used.
-    fn selection(&mut self, _: &Connection, _: &QueueHandle<Self>, _: &WlDataDevice) {}
+    fn selection(&mut self, _: &Connection, _: &QueueHandle<Self>, data_device: &WlDataDevice) {
+        // The data control device reports the same changes.
+        if self.data_control(SelectionTarget::Clipboard).is_some() {
+            return;
+        }
+
+        if let Some(data) = data_device.data::<DataDeviceData>() {
+            let seat = data.seat().clone();
+            self.notify_selection_change(SelectionTarget::Clipboard, &seat);
+        }
+    }
 }
 
 impl DataSourceHandler for State {
@@ -415,15 +1182,15 @@ impl DataSourceHandler for State {
         &mut self,
         _: &Connection,
         _: &QueueHandle<Self>,
-        _: &WlDataSource,
+        source: &WlDataSource,
         mime: String,
         write_pipe: WritePipe,
     ) {
-        self.send_request(SelectionTarget::Clipboard, write_pipe, mime)
+        self.send_request(SelectionTarget::Clipboard, source.id(), write_pipe, mime)
     }
 
     fn cancelled(&mut self, _: &Connection, _: &QueueHandle<Self>, deleted: &WlDataSource) {
-        self.data_sources.retain(|source| source.inner() != deleted)
+        self.source_cancelled(SelectionTarget::Clipboard, deleted.id());
     }
 
     fn accept_mime(
@@ -475,8 +1242,17 @@ impl PrimarySelectionDeviceHandler for State {
         &mut self,
         _: &Connection,
         _: &QueueHandle<Self>,
-        _: &ZwpPrimarySelectionDeviceV1,
+        primary_device: &ZwpPrimarySelectionDeviceV1,
     ) {
+        // The data control device reports the same changes.
+        if self.data_control(SelectionTarget::Primary).is_some() {
+            return;
+        }
+
+        if let Some(data) = primary_device.data::<PrimarySelectionDeviceData>() {
+            let seat = data.seat().clone();
+            self.notify_selection_change(SelectionTarget::Primary, &seat);
+        }
     }
 }
 
@@ -485,11 +1261,11 @@ impl PrimarySelectionSourceHandler for State {
         &mut self,
         _: &Connection,
         _: &QueueHandle<Self>,
-        _: &ZwpPrimarySelectionSourceV1,
+        source: &ZwpPrimarySelectionSourceV1,
         mime: String,
         write_pipe: WritePipe,
     ) {
-        self.send_request(SelectionTarget::Primary, write_pipe, mime);
+        self.send_request(SelectionTarget::Primary, source.id(), write_pipe, mime);
     }
 
     fn cancelled(
@@ -498,7 +1274,7 @@ impl PrimarySelectionSourceHandler for State {
         _: &QueueHandle<Self>,
         deleted: &ZwpPrimarySelectionSourceV1,
     ) {
-        self.primary_sources.retain(|source| source.inner() != deleted)
+        self.source_cancelled(SelectionTarget::Primary, deleted.id());
     }
 }
 
@@ -541,7 +1317,127 @@ delegate_data_device!(State);
 delegate_primary_selection!(State);
 delegate_registry!(State);
 
-#[derive(Debug, Clone, Copy)]
+/// Selection source served by us.
+enum SelectionSource {
+    CopyPaste(CopyPasteSource),
+    Primary(PrimarySelectionSource),
+    DataControl(DataControlSource),
+}
+
+impl SelectionSource {
+    fn id(&self) -> ObjectId {
+        match self {
+            Self::CopyPaste(source) => source.inner().id(),
+            Self::Primary(source) => source.inner().id(),
+            Self::DataControl(source) => source.id(),
+        }
+    }
+}
+
+/// Selection offer for either of the selection targets.
+enum Offer {
+    Clipboard(SelectionOffer),
+    Primary(PrimarySelectionOffer),
+    DataControl(DataControlOffer),
+}
+
+impl Offer {
+    fn with_mime_types<T, F: Fn(&[String]) -> T>(&self, callback: F) -> T {
+        match self {
+            Self::Clipboard(offer) => offer.with_mime_types(callback),
+            Self::Primary(offer) => offer.with_mime_types(callback),
+            Self::DataControl(offer) => offer.with_mime_types(callback),
+        }
+    }
+
+    /// Ensure that the `mime_type` is offered.
+    fn ensure_offered(&self, mime_type: &str) -> Result<()> {
+        if self.with_mime_types(|offered| offered.iter().any(|offered| offered == mime_type)) {
+            Ok(())
+        } else {
+            Err(ClipboardError::UnsupportedMime)
+        }
+    }
+
+    fn receive_to_fd(&self, mime_type: String, write_fd: OwnedFd) {
+        match self {
+            Self::Clipboard(offer) => data_offer::receive_to_fd(offer.inner(), mime_type, write_fd),
+            Self::Primary(offer) => offer.receive_to_fd(mime_type, write_fd),
+            Self::DataControl(offer) => offer.receive_to_fd(mime_type, write_fd),
+        }
+    }
+
+    fn receive(&self, mime_type: String) -> Result<ReadPipe> {
+        match self {
+            Self::Clipboard(offer) => offer.receive(mime_type).map_err(|err| match err {
+                DataOfferError::InvalidReceive => ClipboardError::OfferNotReady,
+                DataOfferError::Io(err) => ClipboardError::Io(err),
+            }),
+            Self::Primary(offer) => Ok(offer.receive(mime_type)?),
+            Self::DataControl(offer) => {
+                let (read_fd, write_fd) = pipe()?;
+                offer.receive_to_fd(mime_type, write_fd);
+                Ok(ReadPipe::from(read_fd))
+            },
+        }
+    }
+}
+
+/// Data requested from the selection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoadRequest {
+    /// Text in the best supported text mime type.
+    Text,
+    /// Raw data in the given mime type.
+    Mime(String),
+}
+
+/// Check of the selection ownership, done once the compositor handled all of
+/// our prior requests.
+#[derive(Debug)]
+pub struct OwnershipCheck {
+    target: SelectionTarget,
+    source: ObjectId,
+    /// Number of the selection changes seen when the new source was set.
+    changes: Option<u64>,
+}
+
+impl Dispatch<WlCallback, OwnershipCheck> for State {
+    fn event(
+        state: &mut State,
+        _: &WlCallback,
+        event: wl_callback::Event,
+        check: &OwnershipCheck,
+        _: &Connection,
+        _: &QueueHandle<State>,
+    ) {
+        if let wl_callback::Event::Done { .. } = event {
+            state.ownership_checked(check);
+        }
+    }
+}
+
+/// Limit of the transfers of the source.
+#[derive(Debug)]
+struct TransferLimit {
+    /// Number of the pastes left.
+    remaining: usize,
+    /// Mime types transferred in the current paste.
+    pasted: Vec<String>,
+    /// Whether the current paste was counted.
+    counted: bool,
+    /// Withdrawal of the source with no pastes left.
+    withdrawal: Option<RegistrationToken>,
+}
+
+impl TransferLimit {
+    fn new(remaining: usize) -> Self {
+        Self { remaining, pasted: Vec::new(), counted: false, withdrawal: None }
+    }
+}
+
+/// The selection to operate on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum SelectionTarget {
     /// The target is clipboard selection.
     Clipboard,
@@ -549,18 +1445,64 @@ pub enum SelectionTarget {
     Primary,
 }
 
+/// The protocol used to access the clipboard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ClipboardBackend {
+    /// The `ext-data-control-v1` protocol, which doesn't require focus.
+    ExtDataControl,
+    /// The `wlr-data-control-unstable-v1` protocol, which doesn't require
+    /// focus.
+    ///
+    /// The primary selection is accessed through the data device when the
+    /// compositor only supports its first version.
+    WlrDataControl,
+    /// The core data device and primary selection protocols, which require
+    /// keyboard focus.
+    DataDevice,
+}
+
 #[derive(Debug, Default)]
 struct ClipboardSeatState {
     keyboard: Option<WlKeyboard>,
     pointer: Option<WlPointer>,
     data_device: Option<DataDevice>,
     primary_device: Option<PrimarySelectionDevice>,
+    data_control_device: Option<DataControlDevice>,
     has_focus: bool,
 
     /// The latest serial used to set the selection content.
     latest_serial: u32,
 }
 
+impl ClipboardSeatState {
+    fn new(data_control_device: Option<DataControlDevice>) -> Self {
+        let mut seat = Self::default();
+        seat.data_control_device = data_control_device;
+        seat
+    }
+
+    /// Get the current selection offer for the given target.
+    fn selection_offer(&self, ty: SelectionTarget) -> Option<Offer> {
+        if let Some(device) = self.data_control_device.as_ref().filter(|device| device.supports(ty))
+        {
+            return device.selection_offer(ty).map(Offer::DataControl);
+        }
+
+        match ty {
+            SelectionTarget::Clipboard => self
+                .data_device
+                .as_ref()
+                .and_then(|data| data.data().selection_offer())
+                .map(Offer::Clipboard),
+            SelectionTarget::Primary => self
+                .primary_device
+                .as_ref()
+                .and_then(|data| data.data().selection_offer())
+                .map(Offer::Primary),
+        }
+    }
+}
+
 impl Drop for ClipboardSeatState {
     fn drop(&mut self) {
         if let Some(keyboard) = self.keyboard.take() {
@@ -577,6 +1519,64 @@ impl Drop for ClipboardSeatState {
     }
 }
 
+/// Convert the text read from the selection into UTF-8 bytes.
+fn text_from_bytes(content: Vec<u8>, mime_type: &str) -> Vec<u8> {
+    let content = match String::from_utf8(content) {
+        Ok(content) => content,
+        Err(err) => String::from_utf8_lossy(err.as_bytes()).into_owned(),
+    };
+
+    // Post-process the content according to mime type.
+    let content = match MimeType::find_allowed(&[mime_type.to_owned()]) {
+        Some(MimeType::TextPlainUtf8 | MimeType::TextPlain) => normalize_to_lf(content),
+        _ => content,
+    };
+
+    content.into_bytes()
+}
+
+/// Load the content served by our own source without going through the
+/// compositor.
+///
+/// Returns `None` for the streamed data, which is read through the pipe like
+/// by any other pasting client.
+fn load_own_content(
+    content: &mut SelectionContent,
+    request: &LoadRequest,
+) -> Result<Option<Vec<u8>>> {
+    let mime_type = match request {
+        LoadRequest::Text => MimeType::find_allowed(content.mime_types())
+            .ok_or(ClipboardError::UnsupportedMime)?
+            .to_string(),
+        LoadRequest::Mime(mime_type) => mime_type.clone(),
+    };
+
+    // Opening the stream could use it up, like for the readers streamed once.
+    if content.is_streamed(&mime_type) {
+        return Ok(None);
+    }
+
+    let data = match content.get(&mime_type).ok_or(ClipboardError::UnsupportedMime)? {
+        SendData::Data(data) => data.to_vec(),
+        SendData::Reader(_) => return Ok(None),
+    };
+
+    Ok(Some(match request {
+        LoadRequest::Text => text_from_bytes(data, &mime_type),
+        LoadRequest::Mime(_) => data,
+    }))
+}
+
+/// Create a pipe, returning its read and write ends.
+pub(crate) fn pipe() -> std::io::Result<(OwnedFd, OwnedFd)> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    unsafe { Ok((OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1]))) }
+}
+
 fn set_non_blocking(raw_fd: RawFd) -> std::io::Result<()> {
     let flags = unsafe { libc::fcntl(raw_fd, libc::F_GETFL) };
 
@@ -591,3 +1591,196 @@ fn set_non_blocking(raw_fd: RawFd) -> std::io::Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::{self, Cursor};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use super::*;
+    use crate::content::tests::ZEROED;
+    use crate::mime::ALLOWED_MIME_TYPES;
+    use crate::test_compositor::{DataControlProtocol, TestCompositor, connect};
+
+    #[test]
+    fn own_text_is_loaded_from_memory() {
+        let content = ClipboardContent::new().with_text("first\r\nsecond");
+        let mut content = SelectionContent::from(content);
+
+        let text = load_own_content(&mut content, &LoadRequest::Text).unwrap();
+        assert_eq!(text.unwrap(), b"first\nsecond");
+    }
+
+    #[test]
+    fn own_content_is_loaded_without_compositor() {
+        let (compositor, _connection, clipboard) = connect(DataControlProtocol::ext());
+
+        clipboard.try_store("first\r\nsecond").unwrap();
+        assert_eq!(clipboard.load().unwrap(), "first\nsecond");
+        assert_eq!(clipboard.load_mime("text/plain;charset=utf-8").unwrap(), b"first\r\nsecond");
+        assert_eq!(compositor.receives(), 0);
+
+        let reader = Box::new(Cursor::new(b"a,b".to_vec()));
+        let content = ClipboardContent::new().with_reader("text/csv", reader);
+        clipboard.try_store_content(SelectionTarget::Clipboard, content).unwrap();
+        assert_eq!(clipboard.load_mime("text/csv").unwrap(), b"a,b");
+        assert_eq!(compositor.receives(), 1);
+    }
+
+    #[test]
+    fn selection_announced_before_cancel_is_not_ours() {
+        let (compositor, _connection, clipboard) = connect(DataControlProtocol::ext());
+        let stored = clipboard.try_store("ours").unwrap();
+        assert!(clipboard.owns_selection());
+
+        // Our source is still alive when the new selection is announced.
+        compositor.set_late_cancel(true);
+        compositor.set_selection(SelectionTarget::Clipboard, &[("text/plain", b"external")]);
+        TestCompositor::wait_for(|| clipboard.load().ok().as_deref() == Some("external"));
+        assert!(!clipboard.owns_selection());
+        TestCompositor::wait_for(|| stored.is_cancelled());
+    }
+
+    #[test]
+    fn hinted_store_once_survives_manager() {
+        let (compositor, _connection, clipboard) = connect(DataControlProtocol::ext());
+        clipboard.store_once("code");
+        TestCompositor::wait_for(|| clipboard.owns_selection());
+
+        // Clipboard manager skipping the content after reading the hint.
+        let hint =
+            compositor.read_selection(SelectionTarget::Clipboard, PASSWORD_MANAGER_HINT_MIME_TYPE);
+        assert_eq!(hint, b"secret");
+        thread::sleep(PASTE_GRACE_PERIOD * 2);
+        assert!(clipboard.owns_selection());
+
+        let text =
+            compositor.read_selection(SelectionTarget::Clipboard, "text/plain;charset=utf-8");
+        assert_eq!(text, b"code");
+        TestCompositor::wait_for(|| {
+            compositor.client_selection(SelectionTarget::Clipboard).is_none()
+        });
+        assert!(!clipboard.owns_selection());
+    }
+
+    #[test]
+    fn paste_in_every_mime_type_is_counted_once() {
+        let (compositor, _connection, clipboard) = connect(DataControlProtocol::ext());
+        let content = ClipboardContent::new().with_text("twice").max_transfers(2);
+        clipboard.try_store_content(SelectionTarget::Clipboard, content).unwrap();
+
+        // Clipboard manager copying the content in every mime type.
+        for mime_type in ALLOWED_MIME_TYPES {
+            let text = compositor.read_selection(SelectionTarget::Clipboard, mime_type);
+            assert_eq!(text, b"twice");
+        }
+        thread::sleep(PASTE_GRACE_PERIOD * 2);
+        assert!(clipboard.owns_selection());
+
+        // The paste reading the same mime type again is the last one.
+        let text = compositor.read_selection(SelectionTarget::Clipboard, "text/plain");
+        assert_eq!(text, b"twice");
+        TestCompositor::wait_for(|| {
+            compositor.client_selection(SelectionTarget::Clipboard).is_none()
+        });
+    }
+
+    #[test]
+    fn large_streams_are_sent() {
+        let (compositor, _connection, clipboard) = connect(DataControlProtocol::ext());
+        let data: Vec<u8> = (0..STREAM_CHUNK_SIZE * 5 + 7).map(|byte| byte as u8).collect();
+
+        let reader = Box::new(Cursor::new(data.clone()));
+        let content = ClipboardContent::new().with_reader("application/octet-stream", reader);
+        clipboard.try_store_content(SelectionTarget::Clipboard, content).unwrap();
+        let read =
+            compositor.read_selection(SelectionTarget::Clipboard, "application/octet-stream");
+        assert_eq!(read, data);
+
+        let path = std::env::temp_dir().join(format!("smithay-clipboard-{}", std::process::id()));
+        fs::write(&path, &data).unwrap();
+        let content = ClipboardContent::new().with_file("application/octet-stream", &path);
+        clipboard.try_store_content(SelectionTarget::Clipboard, content.unwrap()).unwrap();
+        for _ in 0..2 {
+            let read =
+                compositor.read_selection(SelectionTarget::Clipboard, "application/octet-stream");
+            assert_eq!(read, data);
+        }
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn blocking_reader_does_not_stall_clipboard() {
+        /// Reader blocking until its gate is opened.
+        struct GatedReader {
+            gate: Option<mpsc::Receiver<()>>,
+            data: Cursor<Vec<u8>>,
+        }
+
+        impl Read for GatedReader {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                if let Some(gate) = self.gate.take() {
+                    let _ = gate.recv();
+                }
+                self.data.read(buf)
+            }
+        }
+
+        let (_compositor, _connection, clipboard) = connect(DataControlProtocol::ext());
+        let (gate_tx, gate) = mpsc::channel();
+        let reader = GatedReader { gate: Some(gate), data: Cursor::new(b"slow".to_vec()) };
+        let content = ClipboardContent::new().with_reader("text/slow", Box::new(reader));
+        clipboard.try_store_content(SelectionTarget::Clipboard, content).unwrap();
+
+        let load = clipboard.start_load_mime(SelectionTarget::Clipboard, "text/slow");
+        thread::sleep(Duration::from_millis(50));
+        assert!(clipboard.owns_selection());
+        assert!(clipboard.available_mime_types().unwrap().contains(&String::from("text/slow")));
+
+        gate_tx.send(()).unwrap();
+        assert_eq!(load.wait().unwrap(), b"slow");
+    }
+
+    #[test]
+    fn own_stream_is_left_to_pipe() {
+        let opened = Arc::new(AtomicBool::new(false));
+        let provider_opened = opened.clone();
+        let content = ClipboardContent::new().with_stream_provider(["text/csv"], move |_: &str| {
+            provider_opened.store(true, Ordering::Relaxed);
+            io::Result::Ok(Cursor::new(b"a,b".to_vec()))
+        });
+        let mut content = SelectionContent::from(content);
+
+        let request = LoadRequest::Mime(String::from("text/csv"));
+        assert_eq!(load_own_content(&mut content, &request).unwrap(), None);
+        assert!(!opened.load(Ordering::Relaxed));
+
+        let request = LoadRequest::Mime(String::from("text/html"));
+        let err = load_own_content(&mut content, &request).unwrap_err();
+        assert!(matches!(err, ClipboardError::UnsupportedMime));
+    }
+
+    #[test]
+    fn dropped_secrets_are_zeroed() {
+        let zeroed = |secret: &[u8]| ZEROED.lock().unwrap().iter().any(|data| data == secret);
+        let (_compositor, _connection, clipboard) = connect(DataControlProtocol::ext());
+
+        let content =
+            ClipboardContent::new().sensitive().with_text("overwritten").with_text("replaced");
+        assert!(zeroed(b"overwritten"));
+        clipboard.try_store_content(SelectionTarget::Clipboard, content).unwrap();
+        assert_eq!(clipboard.load().unwrap(), "replaced");
+        assert!(!zeroed(b"replaced"));
+
+        clipboard.try_store("public").unwrap();
+        TestCompositor::wait_for(|| zeroed(b"replaced"));
+
+        let content = ClipboardContent::new().with_text("cleared").sensitive();
+        clipboard.try_store_content(SelectionTarget::Clipboard, content).unwrap();
+        assert!(!zeroed(b"cleared"));
+        clipboard.clear();
+        TestCompositor::wait_for(|| zeroed(b"cleared"));
+    }
+}
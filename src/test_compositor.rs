@@ -0,0 +1,508 @@
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, OwnedFd};
+use std::os::unix::net::UnixStream;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use sctk::reexports::client::protocol::wl_seat::WlSeat;
+use sctk::reexports::client::{Connection, Proxy};
+use sctk::reexports::protocols_wlr::data_control::v1::client::zwlr_data_control_manager_v1::ZwlrDataControlManagerV1;
+use sctk::reexports::protocols_wlr::data_control::v1::client::zwlr_data_control_offer_v1::ZwlrDataControlOfferV1;
+use wayland_backend::protocol::{Argument, Interface, Message};
+use wayland_backend::rs::server::{
+    Backend, ClientData, ClientId, GlobalHandler, GlobalId, Handle, ObjectData, ObjectId,
+};
+use wayland_protocols::ext::data_control::v1::client::ext_data_control_manager_v1::ExtDataControlManagerV1;
+use wayland_protocols::ext::data_control::v1::client::ext_data_control_offer_v1::ExtDataControlOfferV1;
+
+use crate::Clipboard;
+use crate::state::{SelectionTarget, pipe};
+
+// Opcodes shared by all the versions of the data control protocol.
+const MANAGER_CREATE_DATA_SOURCE: u16 = 0;
+const MANAGER_GET_DATA_DEVICE: u16 = 1;
+const DEVICE_SET_SELECTION: u16 = 0;
+const DEVICE_SET_PRIMARY_SELECTION: u16 = 2;
+const DEVICE_DATA_OFFER: u16 = 0;
+const DEVICE_SELECTION: u16 = 1;
+const DEVICE_PRIMARY_SELECTION: u16 = 3;
+const SOURCE_OFFER: u16 = 0;
+const SOURCE_SEND: u16 = 0;
+const SOURCE_CANCELLED: u16 = 1;
+const OFFER_RECEIVE: u16 = 0;
+const OFFER_OFFER: u16 = 0;
+
+/// How long to wait for the client to react.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Interfaces of the data control protocol served by the compositor.
+#[derive(Clone, Copy)]
+pub struct DataControlProtocol {
+    pub manager: &'static Interface,
+    pub version: u32,
+    pub offer: &'static Interface,
+}
+
+impl DataControlProtocol {
+    pub fn ext() -> Self {
+        Self {
+            manager: ExtDataControlManagerV1::interface(),
+            version: 1,
+            offer: ExtDataControlOfferV1::interface(),
+        }
+    }
+
+    pub fn wlr(version: u32) -> Self {
+        Self {
+            manager: ZwlrDataControlManagerV1::interface(),
+            version,
+            offer: ZwlrDataControlOfferV1::interface(),
+        }
+    }
+}
+
+/// Connect a clipboard to a compositor without any keyboard focus.
+///
+/// The bindings are dropped in reverse, stopping the clipboard before closing
+/// its connection.
+pub fn connect(protocol: DataControlProtocol) -> (TestCompositor, Connection, Clipboard) {
+    let (compositor, socket) = TestCompositor::spawn(protocol);
+    let connection = Connection::from_socket(socket).unwrap();
+    let clipboard = unsafe { Clipboard::new(connection.backend().display_ptr().cast()) };
+    TestCompositor::wait_for(|| compositor.has_device());
+    (compositor, connection, clipboard)
+}
+
+type Command = Box<dyn FnOnce(&mut Compositor, &Handle) + Send>;
+
+/// In-process compositor serving a seat and the data control protocol.
+///
+/// Selections could be set by the compositor itself, acting as another client.
+pub struct TestCompositor {
+    commands: Sender<Command>,
+    exit: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl TestCompositor {
+    /// Spawn the compositor, returning the socket to connect the client to.
+    pub fn spawn(protocol: DataControlProtocol) -> (Self, UnixStream) {
+        let (server_socket, client_socket) = UnixStream::pair().unwrap();
+        let (commands, commands_rx) = mpsc::channel();
+        let exit = Arc::new(AtomicBool::new(false));
+
+        let thread_exit = exit.clone();
+        let thread = thread::spawn(move || {
+            let mut backend = Backend::<Compositor>::new().unwrap();
+            let mut handle = backend.handle();
+            handle.create_global::<Compositor>(WlSeat::interface(), 7, Arc::new(Global::Seat));
+            handle.create_global::<Compositor>(
+                protocol.manager,
+                protocol.version,
+                Arc::new(Global::Manager),
+            );
+            handle.insert_client(server_socket, Arc::new(TestClient)).unwrap();
+
+            let mut compositor = Compositor::new(protocol);
+            run(&mut backend, &mut compositor, commands_rx, &thread_exit);
+        });
+
+        (Self { commands, exit, thread: Some(thread) }, client_socket)
+    }
+
+    /// Set the selection owned by the compositor with data for each mime type.
+    pub fn set_selection(&self, ty: SelectionTarget, content: &[(&str, &[u8])]) {
+        let content: Vec<_> =
+            content.iter().map(|(mime, data)| (mime.to_string(), data.to_vec())).collect();
+        self.run(move |compositor, handle| {
+            compositor.set_selection(handle, ty, Some(Selection::Compositor(Arc::new(content))));
+        });
+    }
+
+    /// Set the selection owned by the compositor which never sends its data,
+    /// keeping the pipes of the receive requests open instead.
+    pub fn set_stalled_selection(&self, ty: SelectionTarget, mime_types: &[&str]) {
+        let mime_types: Vec<_> = mime_types.iter().map(|mime| mime.to_string()).collect();
+        self.run(move |compositor, handle| {
+            compositor.set_selection(handle, ty, Some(Selection::Stalled(mime_types)));
+        });
+    }
+
+    /// Write to the pipe of the latest receive of the stalled selection, if
+    /// any.
+    ///
+    /// Fails once the client closed the pipe.
+    pub fn write_stalled(&self, data: &[u8]) -> Option<io::Result<()>> {
+        let data = data.to_vec();
+        self.query(move |compositor, _| {
+            let fd = compositor.stalled_receives.last()?;
+            Some(File::from(fd.try_clone().unwrap()).write_all(&data))
+        })
+    }
+
+    /// Clear the selection, like its owner exiting.
+    pub fn clear_selection(&self, ty: SelectionTarget) {
+        self.run(move |compositor, handle| compositor.set_selection(handle, ty, None));
+    }
+
+    /// Announce new selections before cancelling the replaced client source.
+    pub fn set_late_cancel(&self, late_cancel: bool) {
+        self.run(move |compositor, _| compositor.late_cancel = late_cancel);
+    }
+
+    /// Ignore the selections set by the client, like ones with an outdated
+    /// serial.
+    pub fn set_ignore_client_selections(&self, ignore: bool) {
+        self.query(move |compositor, _| compositor.ignore_client_selections = ignore);
+    }
+
+    /// Mime types of the selection set by the client, if any.
+    pub fn client_selection(&self, ty: SelectionTarget) -> Option<Vec<String>> {
+        self.query(move |compositor, _| match compositor.selections.get(&ty) {
+            Some(Selection::Client(source)) => compositor.sources.get(source).cloned(),
+            _ => None,
+        })
+    }
+
+    /// Read the selection set by the client in the given mime type.
+    pub fn read_selection(&self, ty: SelectionTarget, mime_type: &str) -> Vec<u8> {
+        let mime_type = CString::new(mime_type).unwrap();
+        let read_fd = self.query(move |compositor, handle| {
+            let Some(Selection::Client(source)) = compositor.selections.get(&ty) else {
+                return None;
+            };
+
+            let (read_fd, write_fd) = pipe().unwrap();
+            send_event(handle, source, SOURCE_SEND, vec![
+                Argument::Str(Some(Box::new(mime_type))),
+                Argument::Fd(write_fd.as_raw_fd()),
+            ]);
+            Some(read_fd)
+        });
+
+        let mut content = Vec::new();
+        File::from(read_fd.expect("no client selection")).read_to_end(&mut content).unwrap();
+        content
+    }
+
+    /// Number of the receive requests of the client.
+    pub fn receives(&self) -> usize {
+        self.query(|compositor, _| compositor.receives)
+    }
+
+    /// Whether the client created the data control device.
+    pub fn has_device(&self) -> bool {
+        self.query(|compositor, _| !compositor.devices.is_empty())
+    }
+
+    /// Wait until the `condition` holds.
+    pub fn wait_for<F: FnMut() -> bool>(mut condition: F) {
+        let deadline = Instant::now() + TIMEOUT;
+        while !condition() {
+            assert!(Instant::now() < deadline, "timed out waiting for the client");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    fn run<F: FnOnce(&mut Compositor, &Handle) + Send + 'static>(&self, command: F) {
+        self.commands.send(Box::new(command)).unwrap();
+    }
+
+    fn query<T, F>(&self, query: F) -> T
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Compositor, &Handle) -> T + Send + 'static,
+    {
+        let (reply_tx, reply_rx) = mpsc::channel();
+        self.run(move |compositor, handle| {
+            let _ = reply_tx.send(query(compositor, handle));
+        });
+        reply_rx.recv().unwrap()
+    }
+}
+
+impl Drop for TestCompositor {
+    fn drop(&mut self) {
+        self.exit.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Dispatch the client and the commands until asked to exit.
+fn run(
+    backend: &mut Backend<Compositor>,
+    compositor: &mut Compositor,
+    commands: Receiver<Command>,
+    exit: &AtomicBool,
+) {
+    while !exit.load(Ordering::Relaxed) {
+        let mut fds =
+            [libc::pollfd { fd: backend.poll_fd().as_raw_fd(), events: libc::POLLIN, revents: 0 }];
+        unsafe { libc::poll(fds.as_mut_ptr(), 1, 10) };
+
+        let _ = backend.dispatch_all_clients(compositor);
+
+        let handle = backend.handle();
+        while let Ok(command) = commands.try_recv() {
+            command(compositor, &handle);
+        }
+
+        let _ = backend.flush(None);
+    }
+}
+
+/// Content of the selection.
+#[derive(Clone)]
+enum Selection {
+    /// Selection owned by the compositor itself.
+    Compositor(Arc<Vec<(String, Vec<u8>)>>),
+    /// Selection owned by the compositor which never sends its data.
+    Stalled(Vec<String>),
+    /// Selection owned by the client's source.
+    Client(ObjectId),
+}
+
+/// State of the compositor.
+struct Compositor {
+    protocol: DataControlProtocol,
+    devices: Vec<ObjectId>,
+    /// Client sources with their offered mime types.
+    sources: HashMap<ObjectId, Vec<String>>,
+    selections: HashMap<SelectionTarget, Selection>,
+    /// Whether the replaced client source is cancelled after the new selection
+    /// is announced.
+    late_cancel: bool,
+    /// Whether the selections set by the client are ignored.
+    ignore_client_selections: bool,
+    /// Number of the receive requests of the client.
+    receives: usize,
+    /// Pipes of the receive requests of the stalled selection.
+    stalled_receives: Vec<OwnedFd>,
+}
+
+impl Compositor {
+    fn new(protocol: DataControlProtocol) -> Self {
+        Self {
+            protocol,
+            devices: Vec::new(),
+            sources: HashMap::new(),
+            selections: HashMap::new(),
+            late_cancel: false,
+            ignore_client_selections: false,
+            receives: 0,
+            stalled_receives: Vec::new(),
+        }
+    }
+
+    /// Replace the selection, cancelling the replaced client source.
+    fn set_selection(
+        &mut self,
+        handle: &Handle,
+        ty: SelectionTarget,
+        selection: Option<Selection>,
+    ) {
+        let old = match &selection {
+            Some(selection) => self.selections.insert(ty, selection.clone()),
+            None => self.selections.remove(&ty),
+        };
+
+        let cancelled = match old {
+            Some(Selection::Client(old)) => Some(old),
+            _ => None,
+        };
+        if let Some(old) = cancelled.as_ref().filter(|_| !self.late_cancel) {
+            send_event(handle, old, SOURCE_CANCELLED, vec![]);
+        }
+
+        let devices = self.devices.clone();
+        for device in devices {
+            self.offer_selection(handle, &device, ty);
+        }
+
+        if let Some(old) = cancelled.as_ref().filter(|_| self.late_cancel) {
+            send_event(handle, old, SOURCE_CANCELLED, vec![]);
+        }
+    }
+
+    /// Send the current selection to the `device`.
+    fn offer_selection(&self, handle: &Handle, device: &ObjectId, ty: SelectionTarget) {
+        let offer = match self.selections.get(&ty) {
+            Some(selection) => {
+                let client = handle.get_client(device.clone()).unwrap();
+                let data = Arc::new(Object::Offer(selection.clone()));
+                let offer = handle
+                    .create_object::<Compositor>(
+                        client,
+                        self.protocol.offer,
+                        self.protocol.version,
+                        data,
+                    )
+                    .unwrap();
+                send_event(handle, device, DEVICE_DATA_OFFER, vec![Argument::NewId(offer.clone())]);
+
+                let mime_types = match selection {
+                    Selection::Compositor(content) => {
+                        content.iter().map(|(mime_type, _)| mime_type.clone()).collect()
+                    },
+                    Selection::Stalled(mime_types) => mime_types.clone(),
+                    Selection::Client(source) => {
+                        self.sources.get(source).cloned().unwrap_or_default()
+                    },
+                };
+                for mime_type in mime_types {
+                    let mime_type = CString::new(mime_type).unwrap();
+                    send_event(handle, &offer, OFFER_OFFER, vec![Argument::Str(Some(Box::new(
+                        mime_type,
+                    )))]);
+                }
+
+                offer
+            },
+            None => ObjectId::null(),
+        };
+
+        let opcode = match ty {
+            SelectionTarget::Clipboard => DEVICE_SELECTION,
+            SelectionTarget::Primary => DEVICE_PRIMARY_SELECTION,
+        };
+        send_event(handle, device, opcode, vec![Argument::Object(offer)]);
+    }
+}
+
+/// Objects created by the client.
+enum Object {
+    Seat,
+    Manager,
+    Device,
+    Source,
+    Offer(Selection),
+}
+
+impl ObjectData<Compositor> for Object {
+    fn request(
+        self: Arc<Self>,
+        handle: &Handle,
+        compositor: &mut Compositor,
+        _: ClientId,
+        msg: Message<ObjectId, OwnedFd>,
+    ) -> Option<Arc<dyn ObjectData<Compositor>>> {
+        let mut args = msg.args.into_iter();
+        match (&*self, msg.opcode) {
+            (Self::Manager, MANAGER_CREATE_DATA_SOURCE) => {
+                let Some(Argument::NewId(source)) = args.next() else { unreachable!() };
+                compositor.sources.insert(source, Vec::new());
+                return Some(Arc::new(Self::Source));
+            },
+            (Self::Manager, MANAGER_GET_DATA_DEVICE) => {
+                let Some(Argument::NewId(device)) = args.next() else { unreachable!() };
+                compositor.devices.push(device);
+                return Some(Arc::new(Self::Device));
+            },
+            (Self::Device, DEVICE_SET_SELECTION | DEVICE_SET_PRIMARY_SELECTION)
+                if !compositor.ignore_client_selections =>
+            {
+                let Some(Argument::Object(source)) = args.next() else { unreachable!() };
+                let ty = match msg.opcode {
+                    DEVICE_SET_SELECTION => SelectionTarget::Clipboard,
+                    _ => SelectionTarget::Primary,
+                };
+                let selection = (!source.is_null()).then_some(Selection::Client(source));
+                compositor.set_selection(handle, ty, selection);
+            },
+            (Self::Source, SOURCE_OFFER) => {
+                let Some(Argument::Str(Some(mime_type))) = args.next() else { unreachable!() };
+                let mime_type = mime_type.to_string_lossy().into_owned();
+                compositor.sources.entry(msg.sender_id).or_default().push(mime_type);
+            },
+            (Self::Offer(selection), OFFER_RECEIVE) => {
+                let Some(Argument::Str(Some(mime_type))) = args.next() else { unreachable!() };
+                let Some(Argument::Fd(fd)) = args.next() else { unreachable!() };
+                compositor.receives += 1;
+                match selection {
+                    Selection::Compositor(content) => {
+                        let mime_type = mime_type.to_string_lossy();
+                        if let Some((_, data)) = content.iter().find(|(mime, _)| *mime == mime_type)
+                        {
+                            let _ = File::from(fd).write_all(data);
+                        }
+                    },
+                    Selection::Stalled(_) => compositor.stalled_receives.push(fd),
+                    Selection::Client(source) => {
+                        let args =
+                            vec![Argument::Str(Some(mime_type)), Argument::Fd(fd.as_raw_fd())];
+                        send_event(handle, source, SOURCE_SEND, args);
+                    },
+                }
+            },
+            // Destructors and the seat requests.
+            _ => (),
+        }
+
+        None
+    }
+
+    fn destroyed(
+        self: Arc<Self>,
+        handle: &Handle,
+        compositor: &mut Compositor,
+        _: ClientId,
+        object_id: ObjectId,
+    ) {
+        match &*self {
+            Self::Device => compositor.devices.retain(|device| *device != object_id),
+            Self::Source => {
+                compositor.sources.remove(&object_id);
+                let owned: Vec<_> = compositor
+                    .selections
+                    .iter()
+                    .filter(|(_, selection)| {
+                        matches!(selection, Selection::Client(source) if *source == object_id)
+                    })
+                    .map(|(ty, _)| *ty)
+                    .collect();
+                for ty in owned {
+                    compositor.set_selection(handle, ty, None);
+                }
+            },
+            Self::Seat | Self::Manager | Self::Offer(_) => (),
+        }
+    }
+}
+
+/// Globals advertised by the compositor.
+enum Global {
+    Seat,
+    Manager,
+}
+
+impl GlobalHandler<Compositor> for Global {
+    fn bind(
+        self: Arc<Self>,
+        _: &Handle,
+        _: &mut Compositor,
+        _: ClientId,
+        _: GlobalId,
+        _: ObjectId,
+    ) -> Arc<dyn ObjectData<Compositor>> {
+        match *self {
+            Self::Seat => Arc::new(Object::Seat),
+            Self::Manager => Arc::new(Object::Manager),
+        }
+    }
+}
+
+struct TestClient;
+
+impl ClientData for TestClient {}
+
+fn send_event(handle: &Handle, sender: &ObjectId, opcode: u16, args: Vec<Argument<ObjectId, i32>>) {
+    let _ = handle.send_event(Message { sender_id: sender.clone(), opcode, args: args.into() });
+}
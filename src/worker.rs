@@ -1,5 +1,6 @@
-use std::io::{Error, Result};
+use std::fs::File;
 use std::sync::mpsc::Sender;
+use std::time::Duration;
 
 use sctk::reexports::calloop::channel::Channel;
 use sctk::reexports::calloop::{EventLoop, channel};
@@ -7,7 +8,12 @@ use sctk::reexports::calloop_wayland_source::WaylandSource;
 use sctk::reexports::client::Connection;
 use sctk::reexports::client::globals::registry_queue_init;
 
-use crate::state::{SelectionTarget, State};
+use crate::content::ClipboardContent;
+use crate::error::{ClipboardError, Result};
+use crate::event::{SelectionChange, StoredSelection};
+use crate::history::{HistoryEntry, HistoryId};
+use crate::load::LoadId;
+use crate::state::{ClipboardBackend, LoadRequest, SelectionTarget, State};
 
 /// Spawn a clipboard worker, which dispatches its own `EventQueue` and handles
 /// clipboard requests.
@@ -15,37 +21,75 @@ pub fn spawn(
     name: String,
     display: Connection,
     rx_chan: Channel<Command>,
-    worker_replier: Sender<Result<String>>,
 ) -> Option<std::thread::JoinHandle<()>> {
     std::thread::Builder::new()
         .name(name)
         .spawn(move || {
-            worker_impl(display, rx_chan, worker_replier);
+            worker_impl(display, rx_chan);
         })
         .ok()
 }
 
+/// Callback receiving the reply to the clipboard worker thread command.
+pub type Reply<T> = Box<dyn FnOnce(Result<T>) + Send>;
+
 /// Clipboard worker thread command.
-#[derive(Eq, PartialEq)]
 pub enum Command {
     /// Store data to a clipboard.
-    Store(String),
+    Store(ClipboardContent),
     /// Store data to a primary selection.
-    StorePrimary(String),
-    /// Load data from a clipboard.
-    Load,
-    /// Load primary selection.
-    LoadPrimary,
+    StorePrimary(ClipboardContent),
+    /// Store data to the given selection, replying whether it was stored.
+    TryStore(SelectionTarget, ClipboardContent, Sender<Result<StoredSelection>>),
+    /// Load data from the given selection, replying to the given callback.
+    ///
+    /// The load could be cancelled using its `id`, when it has one.
+    Load {
+        id: Option<LoadId>,
+        target: SelectionTarget,
+        request: LoadRequest,
+        reply: Reply<Vec<u8>>,
+    },
+    /// Cancel the in-flight load.
+    CancelLoad(LoadId),
+    /// Load data of the given mime type from a clipboard as a stream.
+    LoadStream(String, Sender<Result<File>>),
+    /// Load data of the given mime type from a primary selection as a stream.
+    LoadPrimaryStream(String, Sender<Result<File>>),
+    /// Get mime types offered by a clipboard.
+    MimeTypes(Sender<Result<Vec<String>>>),
+    /// Get mime types offered by a primary selection.
+    PrimaryMimeTypes(Sender<Result<Vec<String>>>),
+    /// Store data to the given selection, withdrawing it after the duration.
+    StoreWithExpiry(SelectionTarget, ClipboardContent, Duration),
+    /// Withdraw our content from the given selection.
+    Clear(SelectionTarget),
+    /// Check whether the given selection is owned by us.
+    OwnsSelection(SelectionTarget, Sender<Result<bool>>),
+    /// Enable or disable the clipboard manager.
+    ManagerMode(bool, Sender<Result<()>>),
+    /// Keep the clipboard history with the given capacity, or drop it.
+    HistoryCapacity(Option<usize>, Sender<Result<()>>),
+    /// Get the clipboard history entries.
+    History(Sender<Result<Vec<HistoryEntry>>>),
+    /// Load the history entry in the given mime type.
+    LoadHistory(HistoryId, String, Sender<Result<Vec<u8>>>),
+    /// Pin or unpin the history entry.
+    PinHistory(HistoryId, bool, Sender<Result<()>>),
+    /// Remove the history entry.
+    RemoveHistory(HistoryId, Sender<Result<()>>),
+    /// Offer the history entry as the clipboard selection.
+    RestoreHistory(HistoryId, Sender<Result<()>>),
+    /// Get the protocol used to access the clipboard.
+    Backend(Sender<Result<ClipboardBackend>>),
+    /// Subscribe to selection changes.
+    Subscribe(Sender<SelectionChange>),
     /// Shutdown the worker.
     Exit,
 }
 
 /// Handle clipboard requests.
-fn worker_impl(
-    connection: Connection,
-    rx_chan: Channel<Command>,
-    reply_tx: Sender<Result<String>>,
-) {
+fn worker_impl(connection: Connection, rx_chan: Channel<Command>) {
     let (globals, event_queue) = match registry_queue_init(&connection) {
         Ok(data) => data,
         Err(_) => return,
@@ -54,8 +98,12 @@ fn worker_impl(
     let mut event_loop = EventLoop::<State>::try_new().unwrap();
     let loop_handle = event_loop.handle();
 
-    let mut state = match State::new(&globals, &event_queue.handle(), loop_handle.clone(), reply_tx)
-    {
+    let mut state = match State::new(
+        &globals,
+        &event_queue.handle(),
+        loop_handle.clone(),
+        connection.display(),
+    ) {
         Some(state) => state,
         None => return,
     };
@@ -65,25 +113,69 @@ fn worker_impl(
             if let channel::Event::Msg(event) = event {
                 match event {
                     Command::StorePrimary(contents) => {
-                        state.store_selection(SelectionTarget::Primary, contents);
+                        let _ = state.store_selection(SelectionTarget::Primary, contents);
                     },
                     Command::Store(contents) => {
-                        state.store_selection(SelectionTarget::Clipboard, contents);
-                    },
-                    Command::Load if state.data_device_manager_state.is_some() => {
-                        if let Err(err) = state.load_selection(SelectionTarget::Clipboard) {
-                            let _ = state.reply_tx.send(Err(err));
-                        }
-                    },
-                    Command::LoadPrimary if state.data_device_manager_state.is_some() => {
-                        if let Err(err) = state.load_selection(SelectionTarget::Primary) {
-                            let _ = state.reply_tx.send(Err(err));
-                        }
-                    },
-                    Command::Load | Command::LoadPrimary => {
-                        let _ = state
-                            .reply_tx
-                            .send(Err(Error::other("requested selection is not supported")));
+                        let _ = state.store_selection(SelectionTarget::Clipboard, contents);
+                    },
+                    Command::TryStore(target, contents, reply_tx) => {
+                        state.try_store_selection(target, contents, reply_tx);
+                    },
+                    Command::Load { id, target, request, reply } if state.supports(target) => {
+                        state.load_selection(target, request, id, reply);
+                    },
+                    Command::Load { reply, .. } => reply(Err(ClipboardError::ProtocolUnavailable)),
+                    Command::CancelLoad(id) => state.cancel_load(id),
+                    Command::LoadStream(mime, reply_tx) => {
+                        let stream = state.load_selection_stream(SelectionTarget::Clipboard, mime);
+                        let _ = reply_tx.send(stream);
+                    },
+                    Command::LoadPrimaryStream(mime, reply_tx) => {
+                        let stream = state.load_selection_stream(SelectionTarget::Primary, mime);
+                        let _ = reply_tx.send(stream);
+                    },
+                    Command::MimeTypes(reply_tx) => {
+                        let _ =
+                            reply_tx.send(state.selection_mime_types(SelectionTarget::Clipboard));
+                    },
+                    Command::PrimaryMimeTypes(reply_tx) => {
+                        let _ = reply_tx.send(state.selection_mime_types(SelectionTarget::Primary));
+                    },
+                    Command::StoreWithExpiry(target, contents, expiry) => {
+                        let _ = state.store_selection_with_expiry(target, contents, expiry);
+                    },
+                    Command::Clear(target) => state.clear_selection(target),
+                    Command::OwnsSelection(target, reply_tx) => {
+                        let _ = reply_tx.send(Ok(state.owns_selection(target)));
+                    },
+                    Command::ManagerMode(enabled, reply_tx) => {
+                        let _ = reply_tx.send(state.set_manager_mode(enabled));
+                    },
+                    Command::HistoryCapacity(capacity, reply_tx) => {
+                        let _ = reply_tx.send(state.set_history_capacity(capacity));
+                    },
+                    Command::History(reply_tx) => {
+                        let _ = reply_tx.send(Ok(state.history_entries()));
+                    },
+                    Command::LoadHistory(id, mime_type, reply_tx) => {
+                        let _ = reply_tx
+                            .send(state.with_history(|history| history.load(id, &mime_type)));
+                    },
+                    Command::PinHistory(id, pinned, reply_tx) => {
+                        let _ = reply_tx
+                            .send(state.with_history(|history| history.set_pinned(id, pinned)));
+                    },
+                    Command::RemoveHistory(id, reply_tx) => {
+                        let _ = reply_tx.send(state.with_history(|history| history.remove(id)));
+                    },
+                    Command::RestoreHistory(id, reply_tx) => {
+                        let _ = reply_tx.send(state.restore_history(id));
+                    },
+                    Command::Backend(reply_tx) => {
+                        let _ = reply_tx.send(Ok(state.backend()));
+                    },
+                    Command::Subscribe(subscriber) => {
+                        state.selection_subscribers.push(subscriber);
                     },
                     Command::Exit => state.exit = true,
                 }